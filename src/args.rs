@@ -1,10 +1,77 @@
 use std::path::PathBuf;
 
-use clap::{Parser, ValueEnum};
+use clap::{builder::PossibleValue, Parser, ValueEnum};
+
+use crate::nbt::{
+    compression::Compression,
+    tag::{compound::DuplicatePolicy, ParseOptions},
+    Encoding, Flavor,
+};
 
 #[derive(Clone, Debug, ValueEnum)]
 pub enum Format {
+    Nbt,
     Json,
+    Snbt,
+}
+
+impl ValueEnum for Flavor {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Java,
+            Self::JavaNetwork,
+            Self::Bedrock,
+            Self::BedrockNetwork,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Java => PossibleValue::new("java").help("Big-endian, used by Java Edition"),
+            Self::JavaNetwork => PossibleValue::new("java-network").help(
+                "Big-endian with no name on the root tag, used by the Java Edition network \
+                 protocol since 1.20.2",
+            ),
+            Self::Bedrock => {
+                PossibleValue::new("bedrock").help("Little-endian, used by Bedrock Edition files")
+            }
+            Self::BedrockNetwork => PossibleValue::new("bedrock-network").help(
+                "Little-endian with varint ints, longs and lengths, used by the Bedrock Edition \
+                 network protocol",
+            ),
+        })
+    }
+}
+
+impl ValueEnum for DuplicatePolicy {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::First, Self::Last, Self::Error]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::First => PossibleValue::new("first")
+                .help("Keep the tag that came first and drop the new one"),
+            Self::Last => PossibleValue::new("last").help(
+                "Replace the tag that came first with the new one, which is what Minecraft does",
+            ),
+            Self::Error => PossibleValue::new("error").help("Refuse the new tag"),
+        })
+    }
+}
+
+impl ValueEnum for Compression {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Gzip, Self::Zlib, Self::None]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(match self {
+            Self::Gzip => "gzip",
+            Self::Zlib => "zlib",
+            Self::None => "raw",
+        }))
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -13,10 +80,90 @@ pub struct Args {
     pub file: PathBuf,
     #[arg(short, long, value_enum)]
     pub format: Option<Format>,
+    #[arg(short, long, value_enum, default_value = "nbt")]
+    pub input: Format,
     #[arg(short, long)]
     pub output: Option<PathBuf>,
+    /// Byte order of binary NBT, detected from the file if not given
+    #[arg(long, value_enum)]
+    pub flavor: Option<Flavor>,
+    /// Pretty-print SNBT output, indenting by this many spaces
+    #[arg(
+        long,
+        value_name = "SPACES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "4"
+    )]
+    pub pretty: Option<usize>,
+    /// Which tag to keep when a compound has several tags with the same name
+    #[arg(long, value_enum, default_value = "last")]
+    pub duplicates: DuplicatePolicy,
+    /// Refuse NBT files that are larger than this once decompressed
+    #[arg(long, value_name = "BYTES", default_value_t = ParseOptions::default().max_size)]
+    pub max_size: usize,
+    /// Refuse NBT files with a list or array longer than this
+    #[arg(long, value_name = "ELEMENTS", default_value_t = ParseOptions::default().max_len)]
+    pub max_len: usize,
+    /// Refuse NBT files whose tags would take up more memory than this
+    #[arg(long, value_name = "BYTES", default_value_t = ParseOptions::default().max_memory)]
+    pub max_memory: usize,
+    /// Refuse NBT files with compounds and lists nested deeper than this
+    #[arg(long, value_name = "LEVELS", default_value_t = ParseOptions::default().max_depth)]
+    pub max_depth: usize,
+    /// Recover as much as possible from a damaged or truncated NBT file
+    #[arg(long)]
+    pub lenient: bool,
+    /// List the chunks of a region file instead of opening it
+    #[arg(short, long)]
+    pub list: bool,
+}
+
+impl Args {
+    /// Gets the settings for parsing binary NBT.
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            duplicates: self.duplicates,
+            max_size: self.max_size,
+            max_len: self.max_len,
+            max_memory: self.max_memory,
+            max_depth: self.max_depth,
+        }
+    }
 }
 
 pub fn parse() -> Args {
     Args::parse()
 }
+
+/// Reads the encoding typed into the save prompt, as words naming a format
+/// (nbt, snbt or json), a compression and a flavor. What isn't named is kept
+/// from `encoding`, and the format is NBT unless it's named.
+pub fn to_encoding(text: &str, mut encoding: Encoding) -> Option<(Format, Encoding)> {
+    let mut fmt = Format::Nbt;
+    for word in text.split_whitespace() {
+        if let Ok(f) = Format::from_str(word, true) {
+            fmt = f;
+        } else if let Ok(compression) = Compression::from_str(word, true) {
+            encoding.compression = compression;
+        } else if let Ok(flavor) = Flavor::from_str(word, true) {
+            // Only Bedrock files have a level.dat header
+            if flavor != Flavor::Bedrock {
+                encoding.header = None;
+            }
+            encoding.flavor = flavor;
+        } else {
+            return None;
+        }
+    }
+    Some((fmt, encoding))
+}
+
+/// Describes an encoding the way `to_encoding` reads it.
+pub fn fmt_encoding(encoding: &Encoding) -> String {
+    format!(
+        "{} {}",
+        encoding.compression.to_possible_value().unwrap().get_name(),
+        encoding.flavor.to_possible_value().unwrap().get_name()
+    )
+}
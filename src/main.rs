@@ -6,10 +6,16 @@
     clippy::module_name_repetitions
 )]
 
-use std::{fs, io::Write, path::PathBuf};
-use translate::{get_ext, translate};
-use ui::UI;
-use util::UnwrapOrStrErr;
+use args::{Args, Format};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process::exit,
+};
+use translate::{get_ext, parse_json, translate};
+use ui::{FileKind, UI};
+use util::{write_file, UnwrapOrStrErr};
 
 mod args;
 mod nbt;
@@ -17,32 +23,216 @@ mod translate;
 mod ui;
 mod util;
 
-use flate2::write::GzDecoder;
-use nbt::tag::Tag;
+use nbt::{
+    region::Region,
+    snbt::{count_non_finite, parse_snbt},
+    tag::{
+        traversal::{fmt_path, TagTraversal},
+        Parsed, Tag,
+    },
+    Encoding, Flavor,
+};
+
+/// A file that was opened, with what went wrong while reading it.
+struct Opened {
+    tag: Tag,
+    kind: FileKind,
+    // where a damaged file stops being readable, when reading it leniently,
+    // or which chunks of a region could not be read
+    damage: Option<String>,
+    // paths of the tags that were dropped or replaced by a tag of the same
+    // name
+    duplicates: Vec<Vec<TagTraversal>>,
+}
+
+/// Reads the chunks of a region file, or lists them and exits if `--list`
+/// is given.
+fn open_region(args: &Args, bytes: Vec<u8>) -> Opened {
+    let region =
+        Region::new(bytes, args.parse_options()).unwrap_or_err("Could not read region file");
+    if args.list {
+        for chunk in region.chunks() {
+            println!(
+                "{},{}: {} sectors at sector {}, modified at {}",
+                chunk.x, chunk.z, chunk.sectors, chunk.offset, chunk.timestamp
+            );
+        }
+        exit(0);
+    }
+
+    let tree = region.to_tag();
+    Opened {
+        tag: tree.tag,
+        kind: FileKind::Region(region),
+        damage: tree.errors.first().map(|e| {
+            format!(
+                "{} chunk(s) could not be read, the first: {e}",
+                tree.errors.len()
+            )
+        }),
+        duplicates: tree.duplicates,
+    }
+}
+
+/// Reads a single tag from an NBT, JSON or SNBT file. NBT is decompressed
+/// and parsed as it's read from the file, unless it's read leniently.
+fn open_file(args: &Args) -> Opened {
+    let read = || fs::read(&args.file).unwrap_or_err("Could not open file");
+    let options = args.parse_options();
+    // Files converted from text formats are saved as gzipped NBT, in the
+    // given flavor
+    let converted = FileKind::Nbt(Encoding {
+        flavor: args.flavor.unwrap_or(Flavor::Java),
+        ..Encoding::default()
+    });
+    let opened = |parsed: Parsed, kind| Opened {
+        tag: parsed.tag,
+        kind,
+        damage: None,
+        duplicates: parsed.duplicates,
+    };
+
+    match args.input {
+        Format::Nbt if args.lenient => {
+            let (encoding, parsed, error) = Encoding::detect_lenient(&read(), args.flavor, options)
+                .unwrap_or_err("Could not read file");
+            Opened {
+                damage: error.map(|e| format!("File is damaged, recovered up to here: {e}")),
+                ..opened(parsed, FileKind::Nbt(encoding))
+            }
+        }
+        Format::Nbt => {
+            let file = File::open(&args.file).unwrap_or_err("Could not open file");
+            let (encoding, parsed) =
+                Encoding::detect(file, args.flavor, options).unwrap_or_err("Could not read file");
+            opened(parsed, FileKind::Nbt(encoding))
+        }
+        Format::Json => opened(
+            parse_json(
+                &String::from_utf8(read()).unwrap_or_err("Could not read file as UTF-8"),
+                options,
+            )
+            .unwrap_or_err("Could not parse JSON"),
+            converted,
+        ),
+        Format::Snbt => opened(
+            parse_snbt(
+                &String::from_utf8(read()).unwrap_or_err("Could not read file as UTF-8"),
+                options,
+            )
+            .unwrap_or_err("Could not parse SNBT"),
+            converted,
+        ),
+    }
+}
+
+/// Whether `path` is the same file as `input`, which must not be replaced by
+/// what was made of it.
+fn is_input(path: &Path, input: &Path) -> bool {
+    matches!(
+        (fs::canonicalize(path), fs::canonicalize(input)),
+        (Ok(path), Ok(input)) if path == input
+    )
+}
 
 fn main() {
     let args = args::parse();
-    let bytes = fs::read(&args.file).unwrap_or_err("Could not open file");
-    let mut gz = GzDecoder::new(vec![]);
-    gz.write_all(&bytes)
-        .unwrap_or_err("Could not read from bytes");
 
-    let mut nbt = Tag::new(&gz.finish().unwrap_or_err("Could not unzip file"))
-        .unwrap_or_err("Could not parse tag");
+    // Region files have no magic bytes, so they're told apart by extension
+    let is_region = matches!(args.input, Format::Nbt)
+        && args
+            .file
+            .extension()
+            .is_some_and(|ext| ext == "mca" || ext == "mcr");
+    let Opened {
+        tag: mut nbt,
+        kind,
+        damage,
+        duplicates,
+    } = if is_region {
+        open_region(
+            &args,
+            fs::read(&args.file).unwrap_or_err("Could not open file"),
+        )
+    } else {
+        open_file(&args)
+    };
 
     if let Some(fmt) = args.format {
-        let out = translate(&nbt, &fmt);
-        fs::write(
-            args.output.unwrap_or({
-                let mut p = PathBuf::from(args.file.file_stem().unwrap());
-                p.set_extension(get_ext(&fmt));
-                p
-            }),
-            out,
-        )
-        .unwrap_or_err("Could not write to file");
+        if let Some(damage) = &damage {
+            eprintln!("{damage}");
+        }
+        for path in &duplicates {
+            eprintln!("Duplicate tag at {}", fmt_path(path));
+        }
+        if matches!(fmt, Format::Snbt) {
+            let non_finite = count_non_finite(&nbt.payload);
+            if non_finite > 0 {
+                eprintln!(
+                    "{non_finite} NaN or infinite float(s) written as 0 or the largest finite \
+                     float, since SNBT has no way to write them"
+                );
+            }
+        }
+        // NBT files keep their encoding, and regions become a single gzipped
+        // Java file
+        let encoding = match &kind {
+            FileKind::Nbt(encoding) => *encoding,
+            FileKind::Region(_) => Encoding::default(),
+        };
+        let output = args.output.clone().unwrap_or_else(|| {
+            let mut p = PathBuf::from(args.file.file_stem().unwrap());
+            p.set_extension(get_ext(&fmt));
+            p
+        });
+        if is_input(&output, &args.file) {
+            eprintln!(
+                "Refusing to overwrite {}, the file being read; give another path with -o",
+                output.display()
+            );
+            exit(1);
+        }
+        let out = translate(&nbt, &fmt, &encoding, args.pretty, args.max_depth)
+            .unwrap_or_err("Could not encode");
+        let written = write_file(&output, |writer| {
+            writer
+                .write_all(&out)
+                .map_err(|e| format!("Could not write to file: {e}"))
+        });
+        if let Err(e) = written {
+            eprintln!("{e}");
+            exit(1);
+        }
     } else {
-        let mut ui = UI::new(args.file, &mut nbt).unwrap_or_err("Could not create UI");
+        // The editor saves as NBT, so don't overwrite a file of another format
+        let filename = match args.input {
+            Format::Nbt => args.file,
+            Format::Json | Format::Snbt => args
+                .output
+                .unwrap_or(args.file.with_extension(get_ext(&Format::Nbt))),
+        };
+        // The editor shows the tags of a container, so it has nothing to show
+        // for a root that is a single value
+        if !nbt.is_container() {
+            eprintln!(
+                "Cannot edit a root tag that is a single {}, convert it with -f instead",
+                nbt.id
+            );
+            exit(1);
+        }
+        let mut ui =
+            UI::new(filename, kind, &mut nbt, args.max_depth).unwrap_or_err("Could not create UI");
+        // Damage matters more than duplicates, and there is only room for one
+        // message
+        if let Some(damage) = damage {
+            ui.set_message(damage);
+        } else if let Some(first) = duplicates.first() {
+            ui.set_message(format!(
+                "{} duplicate tag(s), the first at {}",
+                duplicates.len(),
+                fmt_path(first)
+            ));
+        }
         ui.mainloop().unwrap_or_err("Could not execute mainloop");
     }
 }
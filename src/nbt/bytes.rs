@@ -1,101 +1,447 @@
-use std::{slice::Iter, string::FromUtf8Error};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+    string::FromUtf8Error,
+};
 
-use crate::nbt::tag::id::TagID;
+use crate::nbt::{
+    mutf8,
+    tag::{
+        id::TagID,
+        traversal::{fmt_path, TagTraversal},
+        ParseOptions,
+    },
+    Flavor,
+};
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum ByteError {
     NextByteError(usize),
     Utf8Error(FromUtf8Error),
+    Mutf8Error,
     InvalidTagID(u8),
+    InvalidVarInt,
+    EndList(usize),
+    NegativeLength(i32),
+    TooLong(usize, usize),
+    TooLarge(usize),
+    TooMuchMemory(usize),
+    TooDeep(usize),
+    DuplicateTag(String),
+    Io(io::Error),
 }
 
-impl ToString for ByteError {
-    fn to_string(&self) -> String {
+impl fmt::Display for ByteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::NextByteError(n) => format!("Cannot read {n} bytes ahead"),
-            Self::Utf8Error(e) => format!("UTF8 Error: {e}"),
-            Self::InvalidTagID(id) => format!("Invalid tag ID byte {id:x?}"),
+            Self::NextByteError(n) => write!(f, "Cannot read {n} bytes ahead"),
+            Self::Utf8Error(e) => write!(f, "UTF8 Error: {e}"),
+            Self::Mutf8Error => write!(f, "Invalid modified UTF-8"),
+            Self::InvalidTagID(id) => write!(f, "Invalid tag ID byte {id:x?}"),
+            Self::InvalidVarInt => write!(f, "Varint is too long"),
+            Self::EndList(len) => write!(f, "List of {len} End tags"),
+            Self::NegativeLength(len) => write!(f, "Negative length {len}"),
+            Self::TooLong(len, max) => write!(f, "Length {len} is over the limit of {max}"),
+            Self::TooLarge(max) => write!(f, "Data is larger than the limit of {max} bytes"),
+            Self::TooMuchMemory(max) => write!(f, "Tags take up over the {max} byte memory limit"),
+            Self::TooDeep(max) => write!(f, "Tags are nested deeper than the limit of {max}"),
+            Self::DuplicateTag(name) => write!(f, "Duplicate tag \"{name}\""),
+            Self::Io(e) => write!(f, "I/O error: {e}"),
         }
     }
 }
 
-pub type ByteResult<T> = Result<T, ByteError>;
+impl Error for ByteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Utf8Error(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-/// A wrapper around a u8 iterator that provides functions to read bytes and turn them into data.
-pub struct NbtBytesIter<'a> {
-    pub iter: &'a mut Iter<'a, u8>,
+/// An error while parsing binary NBT, with the position in the file and in the
+/// tree where it happened.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ByteError,
+    /// Offset of the value that could not be read, from the start of the
+    /// decompressed data
+    pub offset: usize,
+    /// Path to the tag that was being read
+    pub path: Vec<TagTraversal>,
+    /// Type of the tag that was being read
+    pub expected: TagID,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at byte {} while reading {} at {}",
+            self.kind,
+            self.offset,
+            self.expected,
+            fmt_path(&self.path)
+        )
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.kind)
+    }
 }
 
-impl NbtBytesIter<'_> {
-    /// Reads and consumes a specified number of bytes from the iterator. This
-    /// function returns a vector of u8 on success, or a `ByteError::NextByteError`
-    /// on failure (if the iterator reaches its end before all bytes were read).
-    pub fn next_bytes(&mut self, n: usize) -> ByteResult<Vec<u8>> {
+pub type ParseResult<T> = Result<T, ParseError>;
+
+/// A wrapper around a reader that provides functions to read bytes and turn
+/// them into data.
+pub struct NbtReader<R> {
+    reader: R,
+    offset: usize,
+    max_size: usize,
+    max_len: usize,
+    max_depth: usize,
+    // memory charged so far, and its limit
+    memory: usize,
+    max_memory: usize,
+    pub flavor: Flavor,
+    /// Path to the tag being read, kept up to date by the parser for errors
+    pub path: Vec<TagTraversal>,
+    /// Type of the tag being read, kept up to date by the parser for errors
+    pub expected: TagID,
+}
+
+impl<R: Read> NbtReader<R> {
+    pub fn new(reader: R, flavor: Flavor, options: ParseOptions) -> Self {
+        Self {
+            reader,
+            offset: 0,
+            max_size: options.max_size,
+            max_len: options.max_len,
+            max_depth: options.max_depth,
+            memory: 0,
+            max_memory: options.max_memory,
+            flavor,
+            path: vec![],
+            expected: TagID::End,
+        }
+    }
+
+    /// Gets the number of bytes read so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn error(&self, kind: ByteError, offset: usize) -> ParseError {
+        ParseError {
+            kind,
+            offset,
+            path: self.path.clone(),
+            expected: self.expected,
+        }
+    }
+
+    /// Turns an error from the reader into a `ByteError::NextByteError` for
+    /// reading `n` bytes from `offset` if the data ran out.
+    fn read_error(&self, e: io::Error, n: usize, offset: usize) -> ParseError {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            self.error(ByteError::NextByteError(n), offset)
+        } else {
+            self.error(ByteError::Io(e), offset)
+        }
+    }
+
+    /// Fails with a `ByteError::TooLarge` if reading `n` more bytes would go
+    /// over the size limit.
+    fn check_size(&self, n: usize, offset: usize) -> ParseResult<()> {
+        if self.offset.saturating_add(n) > self.max_size {
+            Err(self.error(ByteError::TooLarge(self.max_size), offset))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Charges `bytes` of memory for something that is about to be read, or
+    /// fails with a `ByteError::TooMuchMemory` if it would go over the memory
+    /// limit. Lengths are charged before anything is allocated for them.
+    pub fn charge(&mut self, bytes: usize) -> ParseResult<()> {
+        self.memory = self.memory.saturating_add(bytes);
+        if self.memory > self.max_memory {
+            Err(self.error(ByteError::TooMuchMemory(self.max_memory), self.offset))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fails with a `ByteError::TooDeep` if a container at `depth` would go
+    /// over the depth limit.
+    pub fn check_depth(&self, depth: usize) -> ParseResult<()> {
+        if depth > self.max_depth {
+            Err(self.error(ByteError::TooDeep(self.max_depth), self.offset))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fills `buf`, or fails with a `ByteError::NextByteError` for reading
+    /// `n` bytes from `offset`.
+    fn read_into(&mut self, buf: &mut [u8], n: usize, offset: usize) -> ParseResult<()> {
+        self.check_size(buf.len(), offset)?;
+        match self.reader.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len();
+                Ok(())
+            }
+            Err(e) => Err(self.read_error(e, n, offset)),
+        }
+    }
+
+    /// Takes the next byte, or fails with a `ByteError::NextByteError` for
+    /// reading `n` bytes from `offset`.
+    fn next_byte(&mut self, n: usize, offset: usize) -> ParseResult<u8> {
+        let mut byte = [0];
+        self.read_into(&mut byte, n, offset)?;
+        Ok(byte[0])
+    }
+
+    /// Reads and consumes a specified number of bytes. This function returns
+    /// a vector of u8 on success, or a `ByteError::NextByteError` on failure
+    /// (if the reader reaches its end before all bytes were read). The vector
+    /// grows as data comes in, so a bogus length cannot allocate much.
+    pub fn next_bytes(&mut self, n: usize) -> ParseResult<Vec<u8>> {
+        let offset = self.offset;
+        self.check_size(n, offset)?;
         let mut vec = vec![];
-        for _ in 0..n {
-            vec.push(*self.iter.next().ok_or(ByteError::NextByteError(n))?);
+        match (&mut self.reader).take(n as u64).read_to_end(&mut vec) {
+            Ok(len) if len == n => {
+                self.offset += n;
+                Ok(vec)
+            }
+            Ok(_) => Err(self.error(ByteError::NextByteError(n), offset)),
+            Err(e) => Err(self.read_error(e, n, offset)),
         }
+    }
 
-        Ok(vec)
+    /// Takes the next N bytes and puts them in big-endian order, so that
+    /// numbers can be constructed the same way for every flavor.
+    fn next_ordered<const N: usize>(&mut self) -> ParseResult<[u8; N]> {
+        let offset = self.offset;
+        let mut bytes = [0; N];
+        self.read_into(&mut bytes, N, offset)?;
+        if self.flavor.is_little_endian() {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    /// Takes the bytes of an unsigned LEB128 varint of at most `max_bytes`
+    /// bytes, where each byte holds 7 bits and the high bit is set on every
+    /// byte but the last.
+    fn next_varint(&mut self, max_bytes: usize) -> ParseResult<u64> {
+        let offset = self.offset;
+        let mut n = 0;
+        for i in 0..max_bytes {
+            let byte = self.next_byte(1, offset)?;
+            n |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+
+        Err(self.error(ByteError::InvalidVarInt, offset))
     }
 
     /// Takes the next byte and constructs an i8.
-    pub fn next_i8(&mut self) -> ByteResult<i8> {
-        Ok(i8::from_be_bytes([*self
-            .iter
-            .next()
-            .ok_or(ByteError::NextByteError(1))?]))
+    pub fn next_i8(&mut self) -> ParseResult<i8> {
+        let offset = self.offset;
+        Ok(i8::from_be_bytes([self.next_byte(1, offset)?]))
     }
 
-    /// Takes the next 2 bytes and constructs a big-endian i16.
-    pub fn next_i16(&mut self) -> ByteResult<i16> {
-        Ok(i16::from_be_bytes(self.next_bytes(2)?.try_into().unwrap()))
+    /// Takes the next 2 bytes and constructs an i16.
+    pub fn next_i16(&mut self) -> ParseResult<i16> {
+        Ok(i16::from_be_bytes(self.next_ordered()?))
     }
 
-    /// Takes the next 2 bytes and constructs a big-endian u16.
-    pub fn next_u16(&mut self) -> ByteResult<u16> {
-        Ok(u16::from_be_bytes(self.next_bytes(2)?.try_into().unwrap()))
+    /// Takes the next 2 bytes and constructs a u16.
+    pub fn next_u16(&mut self) -> ParseResult<u16> {
+        Ok(u16::from_be_bytes(self.next_ordered()?))
     }
 
-    /// Takes the next 4 bytes and constructs a big-endian i32.
-    pub fn next_i32(&mut self) -> ByteResult<i32> {
-        Ok(i32::from_be_bytes(self.next_bytes(4)?.try_into().unwrap()))
+    /// Takes the next 4 bytes and constructs an i32. In network NBT, this is a
+    /// zigzag-encoded varint instead.
+    pub fn next_i32(&mut self) -> ParseResult<i32> {
+        if self.flavor == Flavor::BedrockNetwork {
+            let n = self.next_varint(5)? as u32;
+            Ok((n >> 1) as i32 ^ -((n & 1) as i32))
+        } else {
+            Ok(i32::from_be_bytes(self.next_ordered()?))
+        }
     }
 
-    /// Takes the next 8 bytes and constructs a big-endian i64.
-    pub fn next_i64(&mut self) -> ByteResult<i64> {
-        Ok(i64::from_be_bytes(self.next_bytes(8)?.try_into().unwrap()))
+    /// Takes the next 8 bytes and constructs an i64. In network NBT, this is a
+    /// zigzag-encoded varint instead.
+    pub fn next_i64(&mut self) -> ParseResult<i64> {
+        if self.flavor == Flavor::BedrockNetwork {
+            let n = self.next_varint(10)?;
+            Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+        } else {
+            Ok(i64::from_be_bytes(self.next_ordered()?))
+        }
+    }
+
+    /// Takes the next 4 bytes and constructs an f32.
+    pub fn next_f32(&mut self) -> ParseResult<f32> {
+        Ok(f32::from_be_bytes(self.next_ordered()?))
+    }
+
+    /// Takes the next 8 bytes and constructs an f64.
+    pub fn next_f64(&mut self) -> ParseResult<f64> {
+        Ok(f64::from_be_bytes(self.next_ordered()?))
     }
 
-    /// Takes the next 4 bytes and constructs a big-endian f32.
-    pub fn next_f32(&mut self) -> ByteResult<f32> {
-        Ok(f32::from_be_bytes(self.next_bytes(4)?.try_into().unwrap()))
+    /// Takes the length of a list or array, which must not be negative or
+    /// over the length limit.
+    pub fn next_len(&mut self) -> ParseResult<usize> {
+        let offset = self.offset;
+        let len = self.next_i32()?;
+        if len < 0 {
+            Err(self.error(ByteError::NegativeLength(len), offset))
+        } else if len as usize > self.max_len {
+            Err(self.error(ByteError::TooLong(len as usize, self.max_len), offset))
+        } else {
+            Ok(len as usize)
+        }
     }
 
-    /// Takes the next 8 bytes and constructs a big-endian f64.
-    pub fn next_f64(&mut self) -> ByteResult<f64> {
-        Ok(f64::from_be_bytes(self.next_bytes(8)?.try_into().unwrap()))
+    /// Takes the element type and length of a list. Only an empty list may
+    /// have the type End, since End tags have no payload.
+    pub fn next_list_header(&mut self) -> ParseResult<(TagID, usize)> {
+        let offset = self.offset;
+        let id = self.next_id()?;
+        let len = self.next_len()?;
+        if id == TagID::End && len > 0 {
+            Err(self.error(ByteError::EndList(len), offset))
+        } else {
+            Ok((id, len))
+        }
+    }
+
+    /// Takes the length of an array followed by all of its elements of `N`
+    /// bytes at once, and constructs each element with `from_be_bytes`.
+    fn next_array<const N: usize, T>(
+        &mut self,
+        from_be_bytes: fn([u8; N]) -> T,
+    ) -> ParseResult<Vec<T>> {
+        let len = self.next_len()?;
+        self.charge(len.saturating_mul(N))?;
+        let little_endian = self.flavor.is_little_endian();
+        Ok(self
+            .next_bytes(len.saturating_mul(N))?
+            .chunks_exact(N)
+            .map(|chunk| {
+                let mut bytes: [u8; N] = chunk.try_into().unwrap();
+                if little_endian {
+                    bytes.reverse();
+                }
+                from_be_bytes(bytes)
+            })
+            .collect())
+    }
+
+    /// Takes a byte array.
+    pub fn next_i8_array(&mut self) -> ParseResult<Vec<i8>> {
+        self.next_array(i8::from_be_bytes)
+    }
+
+    /// Takes an int array, which holds varints in network NBT.
+    pub fn next_i32_array(&mut self) -> ParseResult<Vec<i32>> {
+        if self.flavor == Flavor::BedrockNetwork {
+            let len = self.next_len()?;
+            self.charge(len.saturating_mul(4))?;
+            (0..len).map(|_| self.next_i32()).collect()
+        } else {
+            self.next_array(i32::from_be_bytes)
+        }
+    }
+
+    /// Takes a long array, which holds varints in network NBT.
+    pub fn next_i64_array(&mut self) -> ParseResult<Vec<i64>> {
+        if self.flavor == Flavor::BedrockNetwork {
+            let len = self.next_len()?;
+            self.charge(len.saturating_mul(8))?;
+            (0..len).map(|_| self.next_i64()).collect()
+        } else {
+            self.next_array(i64::from_be_bytes)
+        }
     }
 
     /// Gets the next bytes that represent a string. The way this is done is it
-    /// reads a 2-byte big-endian u16 that represents the number of bytes the
-    /// string contains, in UTF-8 format.
-    pub fn next_str(&mut self) -> ByteResult<String> {
-        let name_len = self.next_u16()?;
+    /// reads a 2-byte u16 (or an unsigned varint in network NBT) that
+    /// represents the number of bytes the string contains, in UTF-8 format
+    /// (or modified UTF-8 in Java flavors).
+    pub fn next_str(&mut self) -> ParseResult<String> {
+        let name_len = if self.flavor == Flavor::BedrockNetwork {
+            self.next_varint(5)? as usize
+        } else {
+            self.next_u16()?.into()
+        };
+        self.charge(name_len)?;
+        let offset = self.offset;
         if name_len == 0 {
             Ok(String::new())
+        } else if self.flavor.has_modified_utf8() {
+            mutf8::decode(self.next_bytes(name_len)?)
+                .ok_or_else(|| self.error(ByteError::Mutf8Error, offset))
         } else {
-            String::from_utf8(self.next_bytes(name_len.into())?).map_err(ByteError::Utf8Error)
+            String::from_utf8(self.next_bytes(name_len)?)
+                .map_err(|e| self.error(ByteError::Utf8Error(e), offset))
         }
     }
 
     /// Gets the next bytes and transforms it into a `TagID`. Since there are more
     /// possible u8 values than `TagID` values, this returns the `TagID` on success
     /// and a `ByteError::InvalidTagID` on failure.
-    pub fn next_id(&mut self) -> ByteResult<TagID> {
-        (*self.iter.next().ok_or(ByteError::NextByteError(1))?)
+    pub fn next_id(&mut self) -> ParseResult<TagID> {
+        let offset = self.offset;
+        self.next_byte(1, offset)?
             .try_into()
-            .map_err(ByteError::InvalidTagID)
+            .map_err(|id| self.error(ByteError::InvalidTagID(id), offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(bytes: &[u8]) -> NbtReader<&[u8]> {
+        NbtReader::new(bytes, Flavor::BedrockNetwork, ParseOptions::default())
+    }
+
+    #[test]
+    fn varint_edge_values() {
+        assert_eq!(reader(&[0x00]).next_i32().unwrap(), 0);
+        assert_eq!(reader(&[0x01]).next_i32().unwrap(), -1);
+        assert_eq!(
+            reader(&[0xfe, 0xff, 0xff, 0xff, 0x0f]).next_i32().unwrap(),
+            i32::MAX
+        );
+        assert_eq!(
+            reader(&[0xff, 0xff, 0xff, 0xff, 0x0f]).next_i32().unwrap(),
+            i32::MIN
+        );
+        let min = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(reader(&min).next_i64().unwrap(), i64::MIN);
+    }
+
+    #[test]
+    fn varint_too_long() {
+        let long = [0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        let e = reader(&long).next_i32().unwrap_err();
+        assert!(matches!(e.kind, ByteError::InvalidVarInt));
     }
 }
@@ -0,0 +1,151 @@
+use flate2::{
+    bufread,
+    write::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder},
+};
+use std::io::{self, BufReader, Read, Write};
+
+/// The ways an NBT file can be compressed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    Gzip,
+    Zlib,
+    None,
+}
+
+impl Compression {
+    /// Sniffs the compression from the magic bytes at the start of a file.
+    /// Neither header can be mistaken for uncompressed NBT, since their first
+    /// byte is never a valid tag ID.
+    pub fn detect(bytes: &[u8]) -> Self {
+        match bytes {
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            // Deflate with a window of at least 512 bytes and a valid header
+            // checksum. The smallest window is excluded, since its header byte
+            // is the ID of a string tag.
+            &[cmf, flg, ..]
+                if cmf & 0x0f == 8
+                    && (1..=7).contains(&(cmf >> 4))
+                    && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0 =>
+            {
+                Self::Zlib
+            }
+            _ => Self::None,
+        }
+    }
+
+    /// Wraps a reader so that it's decompressed as it's read.
+    pub fn reader<'a>(self, reader: impl Read + 'a) -> Box<dyn Read + 'a> {
+        let reader = BufReader::new(reader);
+        match self {
+            Self::Gzip => Box::new(BufReader::new(bufread::GzDecoder::new(reader))),
+            Self::Zlib => Box::new(BufReader::new(bufread::ZlibDecoder::new(reader))),
+            Self::None => Box::new(reader),
+        }
+    }
+
+    /// Decompresses as much of a damaged file as possible, up to `limit`
+    /// bytes. The error that stopped decompression, if any, is returned along
+    /// with the data.
+    pub fn decompress_partial(self, bytes: &[u8], limit: usize) -> (Vec<u8>, Option<io::Error>) {
+        let mut out = Limited::new(limit);
+        match self {
+            Self::Gzip => {
+                let mut gz = GzDecoder::new(out);
+                let res = gz.write_all(bytes).and_then(|()| gz.try_finish());
+                (gz.get_ref().bytes.clone(), res.err())
+            }
+            Self::Zlib => {
+                let mut zlib = ZlibDecoder::new(out);
+                let res = zlib.write_all(bytes).and_then(|()| zlib.try_finish());
+                (zlib.get_ref().bytes.clone(), res.err())
+            }
+            Self::None => {
+                let res = out.write_all(bytes);
+                (out.bytes, res.err())
+            }
+        }
+    }
+
+    /// Wraps a writer so that what is written to it is compressed.
+    pub fn writer<W: Write>(self, writer: W) -> Compressor<W> {
+        match self {
+            Self::Gzip => Compressor::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+            Self::Zlib => {
+                Compressor::Zlib(ZlibEncoder::new(writer, flate2::Compression::default()))
+            }
+            Self::None => Compressor::None(writer),
+        }
+    }
+}
+
+/// A vector that refuses to grow past a limit, so that a small compressed
+/// file cannot expand to fill the memory.
+struct Limited {
+    bytes: Vec<u8>,
+    limit: usize,
+}
+
+impl Limited {
+    fn new(limit: usize) -> Self {
+        Self {
+            bytes: vec![],
+            limit,
+        }
+    }
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.limit - self.bytes.len();
+        if room == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Data is larger than the limit of {} bytes", self.limit),
+            ));
+        }
+        let len = buf.len().min(room);
+        self.bytes.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer that compresses its data before passing it on. `finish` must be
+/// called to write the end of the compressed stream.
+pub enum Compressor<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zlib(ZlibEncoder<W>),
+    None(W),
+}
+
+impl<W: Write> Compressor<W> {
+    /// Writes the end of the compressed stream and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Gzip(gz) => gz.finish(),
+            Self::Zlib(zlib) => zlib.finish(),
+            Self::None(writer) => Ok(writer),
+        }
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(gz) => gz.write(buf),
+            Self::Zlib(zlib) => zlib.write(buf),
+            Self::None(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Gzip(gz) => gz.flush(),
+            Self::Zlib(zlib) => zlib.flush(),
+            Self::None(writer) => writer.flush(),
+        }
+    }
+}
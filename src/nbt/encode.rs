@@ -1,58 +1,366 @@
-use super::tag::{id::TagID, payload::TagPayload, Tag};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt,
+    io::{self, Write},
+};
 
-fn encode_payload(payload: &TagPayload) -> Vec<u8> {
-    let mut bytes = vec![];
-    match payload {
-        TagPayload::End => (),
-        TagPayload::Byte(n) => bytes.extend_from_slice(&n.to_be_bytes()),
-        TagPayload::Short(n) => bytes.extend_from_slice(&n.to_be_bytes()),
-        TagPayload::Int(n) => bytes.extend_from_slice(&n.to_be_bytes()),
-        TagPayload::Long(n) => bytes.extend_from_slice(&n.to_be_bytes()),
-        TagPayload::Float(n) => bytes.extend_from_slice(&n.to_be_bytes()),
-        TagPayload::Double(n) => bytes.extend_from_slice(&n.to_be_bytes()),
-        TagPayload::ByteArray(v) | TagPayload::IntArray(v) | TagPayload::LongArray(v) => {
-            bytes.extend_from_slice(&(v.len() as u32).to_be_bytes());
-            for b in v {
-                bytes.append(&mut encode_payload(b));
+use super::{
+    mutf8,
+    tag::{id::TagID, payload::TagPayload, Tag},
+    Flavor,
+};
+
+#[derive(Debug)]
+pub enum EncodeError {
+    Io(io::Error),
+    /// A string whose length in bytes is over the largest length its prefix
+    /// can hold
+    StringTooLong(usize, usize),
+    /// A list or array with more elements than its length can hold
+    TooManyElements(usize),
+    /// Compounds and lists nested deeper than Minecraft accepts
+    TooDeep(usize),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::StringTooLong(len, max) => {
+                write!(f, "String of {len} bytes is over the limit of {max} bytes")
             }
+            Self::TooManyElements(len) => {
+                write!(f, "Cannot store {len} elements in a list or array")
+            }
+            Self::TooDeep(max) => write!(f, "Tags are nested deeper than the limit of {max}"),
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EncodeError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+pub type EncodeResult<T> = Result<T, EncodeError>;
+
+/// A wrapper around a writer that provides functions to turn data into bytes.
+/// Tags are written as they are traversed, without building them in memory.
+pub struct NbtWriter<W> {
+    writer: W,
+    flavor: Flavor,
+    max_depth: usize,
+}
+
+impl<W: Write> NbtWriter<W> {
+    /// Makes a writer that refuses tags nested deeper than `max_depth`, which
+    /// should be the limit the tag was read with.
+    pub fn new(writer: W, flavor: Flavor, max_depth: usize) -> Self {
+        Self {
+            writer,
+            flavor,
+            max_depth,
         }
-        TagPayload::String(s) => {
-            bytes.extend_from_slice(&(s.len() as u16).to_be_bytes());
-            bytes.extend_from_slice(s.as_bytes());
+    }
+
+    /// Writes the big-endian bytes of a number in the byte order of the
+    /// flavor.
+    fn write_ordered<const N: usize>(&mut self, mut bytes: [u8; N]) -> EncodeResult<()> {
+        if self.flavor.is_little_endian() {
+            bytes.reverse();
+        }
+        Ok(self.writer.write_all(&bytes)?)
+    }
+
+    /// Writes an unsigned LEB128 varint.
+    fn write_varint(&mut self, mut n: u64) -> EncodeResult<()> {
+        let mut bytes = [0; 10];
+        let mut len = 0;
+        while n >= 0x80 {
+            bytes[len] = (n & 0x7f) as u8 | 0x80;
+            n >>= 7;
+            len += 1;
+        }
+        bytes[len] = n as u8;
+        Ok(self.writer.write_all(&bytes[..=len])?)
+    }
+
+    /// Writes an i32, which is a zigzag-encoded varint in network NBT.
+    fn write_int(&mut self, n: i32) -> EncodeResult<()> {
+        if self.flavor == Flavor::BedrockNetwork {
+            self.write_varint(u64::from(((n << 1) ^ (n >> 31)) as u32))
+        } else {
+            self.write_ordered(n.to_be_bytes())
+        }
+    }
+
+    /// Writes an i64, which is a zigzag-encoded varint in network NBT.
+    fn write_long(&mut self, n: i64) -> EncodeResult<()> {
+        if self.flavor == Flavor::BedrockNetwork {
+            self.write_varint(((n << 1) ^ (n >> 63)) as u64)
+        } else {
+            self.write_ordered(n.to_be_bytes())
+        }
+    }
+
+    /// Writes the length of a string and its UTF-8 bytes, which are in
+    /// modified UTF-8 in Java flavors. The length is a u16, or an unsigned
+    /// varint that is read as a u32 in network NBT.
+    fn write_str(&mut self, s: &str) -> EncodeResult<()> {
+        let bytes = if self.flavor.has_modified_utf8() {
+            mutf8::encode(s)
+        } else {
+            Cow::Borrowed(s.as_bytes())
+        };
+        if self.flavor == Flavor::BedrockNetwork {
+            let len = u32::try_from(bytes.len())
+                .map_err(|_| EncodeError::StringTooLong(bytes.len(), u32::MAX as usize))?;
+            self.write_varint(u64::from(len))?;
+        } else {
+            let len = u16::try_from(bytes.len())
+                .map_err(|_| EncodeError::StringTooLong(bytes.len(), u16::MAX as usize))?;
+            self.write_ordered(len.to_be_bytes())?;
         }
-        TagPayload::List(id, v) => {
-            bytes.push(*id as u8);
-            bytes.extend_from_slice(&(v.len() as i32).to_be_bytes());
-            for e in v {
-                bytes.append(&mut encode_payload(e));
+        Ok(self.writer.write_all(&bytes)?)
+    }
+
+    /// Writes the length of a list or array, which is an i32.
+    fn write_len(&mut self, len: usize) -> EncodeResult<()> {
+        let len = i32::try_from(len).map_err(|_| EncodeError::TooManyElements(len))?;
+        self.write_int(len)
+    }
+
+    /// Writes the length of an array followed by its elements, using
+    /// `to_be_bytes` to get the bytes of each element. The elements go through
+    /// a buffer on the stack, so that they're written a few thousand bytes at
+    /// a time without allocating.
+    fn write_array<const N: usize, T: Copy>(
+        &mut self,
+        v: &[T],
+        to_be_bytes: fn(T) -> [u8; N],
+    ) -> EncodeResult<()> {
+        self.write_len(v.len())?;
+        let mut buf = [0; 4096];
+        for elements in v.chunks(buf.len() / N) {
+            for (bytes, &n) in buf.chunks_exact_mut(N).zip(elements) {
+                bytes.copy_from_slice(&to_be_bytes(n));
+                if self.flavor.is_little_endian() {
+                    bytes.reverse();
+                }
             }
+            self.writer.write_all(&buf[..elements.len() * N])?;
         }
-        TagPayload::Compound(tags) => {
-            for tag in tags {
-                bytes.append(&mut encode_tag(tag));
+        Ok(())
+    }
+
+    /// Writes a payload that is nested `depth` containers deep.
+    fn write_payload(&mut self, payload: &TagPayload, depth: usize) -> EncodeResult<()> {
+        match payload {
+            TagPayload::End => Ok(()),
+            TagPayload::Byte(n) => Ok(self.writer.write_all(&n.to_be_bytes())?),
+            TagPayload::Short(n) => self.write_ordered(n.to_be_bytes()),
+            TagPayload::Int(n) => self.write_int(*n),
+            TagPayload::Long(n) => self.write_long(*n),
+            TagPayload::Float(n) => self.write_ordered(n.to_be_bytes()),
+            TagPayload::Double(n) => self.write_ordered(n.to_be_bytes()),
+            TagPayload::ByteArray(v) => self.write_array(v, i8::to_be_bytes),
+            TagPayload::IntArray(v) if self.flavor == Flavor::BedrockNetwork => {
+                self.write_len(v.len())?;
+                v.iter().try_for_each(|&n| self.write_int(n))
+            }
+            TagPayload::IntArray(v) => self.write_array(v, i32::to_be_bytes),
+            TagPayload::LongArray(v) if self.flavor == Flavor::BedrockNetwork => {
+                self.write_len(v.len())?;
+                v.iter().try_for_each(|&n| self.write_long(n))
+            }
+            TagPayload::LongArray(v) => self.write_array(v, i64::to_be_bytes),
+            TagPayload::String(s) => self.write_str(s),
+            TagPayload::List(_) | TagPayload::Compound(_) if depth > self.max_depth => {
+                Err(EncodeError::TooDeep(self.max_depth))
+            }
+            TagPayload::List(list) => {
+                self.writer.write_all(&[list.id() as u8])?;
+                self.write_len(list.len())?;
+                list.iter()
+                    .try_for_each(|e| self.write_payload(e, depth + 1))
             }
+            TagPayload::Compound(tags) => {
+                for tag in tags {
+                    self.write_named(&tag.name, &tag.payload, true, depth + 1)?;
+                }
+                Ok(self.writer.write_all(&[TagID::End as u8])?)
+            }
+        }
+    }
+
+    fn write_named(
+        &mut self,
+        name: &str,
+        payload: &TagPayload,
+        named: bool,
+        depth: usize,
+    ) -> EncodeResult<()> {
+        // The ID is taken from the payload, in case they don't match
+        let id = TagID::from(payload);
+        self.writer.write_all(&[id as u8])?;
+
+        if named && id != TagID::End {
+            self.write_str(name)?;
         }
+
+        self.write_payload(payload, depth)
     }
 
-    bytes
+    /// Writes a root tag with the given name and payload, so that a payload
+    /// can be written under another name without copying it. The name is
+    /// left out if the flavor has a nameless root.
+    pub fn write_root(&mut self, name: &str, payload: &TagPayload) -> EncodeResult<()> {
+        self.write_named(name, payload, self.flavor.has_root_name(), 0)
+    }
+
+    /// Writes a root tag.
+    pub fn write_tag(&mut self, tag: &Tag) -> EncodeResult<()> {
+        self.write_root(&tag.name, &tag.payload)
+    }
 }
 
-pub fn encode_tag(input: &Tag) -> Vec<u8> {
-    let mut tag = input.clone();
-    let mut bytes = vec![];
+/// A writer that only counts the bytes written to it.
+struct Counter(usize);
 
-    // Tag ID
-    tag.id = (&tag.payload).into();
-    bytes.push(tag.id as u8);
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
 
-    // Handle tag name
-    if tag.id != TagID::End {
-        bytes.extend_from_slice(&(tag.name.len() as u16).to_be_bytes());
-        bytes.extend_from_slice(tag.name.as_bytes());
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
+}
+
+/// Gets the length of a root tag once encoded, without storing its bytes.
+/// This only fails if the tag cannot be encoded.
+pub fn encoded_len(tag: &Tag, flavor: Flavor, max_depth: usize) -> EncodeResult<usize> {
+    let mut counter = Counter(0);
+    NbtWriter::new(&mut counter, flavor, max_depth).write_tag(tag)?;
+    Ok(counter.0)
+}
 
-    // Payload
-    bytes.append(&mut encode_payload(&tag.payload));
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
 
-    bytes
+    use super::*;
+    use crate::nbt::{
+        bytes::NbtReader,
+        compression::Compression,
+        read_header,
+        tag::{compound::Compound, ParseOptions, MAX_DEPTH},
+        Encoding,
+    };
+
+    fn varint_bytes(n: i64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut writer = NbtWriter::new(&mut bytes, Flavor::BedrockNetwork, MAX_DEPTH);
+        match i32::try_from(n) {
+            Ok(n) => writer.write_int(n).unwrap(),
+            Err(_) => writer.write_long(n).unwrap(),
+        }
+        bytes
+    }
+
+    #[test]
+    fn varint_edge_values() {
+        assert_eq!(varint_bytes(0), [0x00]);
+        assert_eq!(varint_bytes(-1), [0x01]);
+        assert_eq!(
+            varint_bytes(i32::MAX.into()),
+            [0xfe, 0xff, 0xff, 0xff, 0x0f]
+        );
+        assert_eq!(
+            varint_bytes(i32::MIN.into()),
+            [0xff, 0xff, 0xff, 0xff, 0x0f]
+        );
+        assert_eq!(
+            varint_bytes(i64::MIN),
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for n in [0, -1, 1, 63, -64, 64, i32::MAX, i32::MIN] {
+            let bytes = varint_bytes(n.into());
+            let mut reader =
+                NbtReader::new(&bytes[..], Flavor::BedrockNetwork, ParseOptions::default());
+            assert_eq!(reader.next_i32().unwrap(), n);
+        }
+        for n in [i64::MIN, i64::MAX, i64::from(i32::MAX) + 1] {
+            let mut bytes = Vec::new();
+            NbtWriter::new(&mut bytes, Flavor::BedrockNetwork, MAX_DEPTH)
+                .write_long(n)
+                .unwrap();
+            let mut reader =
+                NbtReader::new(&bytes[..], Flavor::BedrockNetwork, ParseOptions::default());
+            assert_eq!(reader.next_i64().unwrap(), n);
+        }
+    }
+
+    fn level_dat() -> Tag {
+        let mut tags = Compound::new();
+        tags.insert(Tag {
+            id: TagID::Int,
+            name: "StorageVersion".to_string(),
+            payload: TagPayload::Int(10),
+        });
+        Tag {
+            id: TagID::Compound,
+            name: String::new(),
+            payload: TagPayload::Compound(tags),
+        }
+    }
+
+    #[test]
+    fn level_dat_header_has_length_of_body() {
+        let encoding = Encoding {
+            compression: Compression::None,
+            flavor: Flavor::Bedrock,
+            header: Some(10),
+        };
+        let bytes = encoding.encode(&level_dat(), MAX_DEPTH).unwrap();
+        assert_eq!(bytes[0..4], 10u32.to_le_bytes());
+        assert_eq!(bytes[4..8], (bytes.len() as u32 - 8).to_le_bytes());
+        // The body is little-endian, so the string length comes low byte first
+        assert_eq!(bytes[8..11], [TagID::Compound as u8, 0, 0]);
+
+        let (detected, parsed) =
+            Encoding::detect(Cursor::new(&bytes), None, ParseOptions::default()).unwrap();
+        assert_eq!(detected.flavor, Flavor::Bedrock);
+        assert_eq!(detected.header, Some(10));
+        assert_eq!(parsed.tag, level_dat());
+    }
+
+    #[test]
+    fn level_dat_header_is_only_read_if_length_matches() {
+        let encoding = Encoding {
+            compression: Compression::None,
+            flavor: Flavor::Bedrock,
+            header: Some(10),
+        };
+        let mut bytes = encoding.encode(&level_dat(), MAX_DEPTH).unwrap();
+        assert_eq!(read_header(&bytes), Some(10));
+        bytes[4] += 1;
+        assert_eq!(read_header(&bytes), None);
+    }
 }
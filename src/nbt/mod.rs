@@ -1,3 +1,260 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Cursor, Read, Seek, Write},
+};
+
+use self::{
+    bytes::ParseError,
+    compression::Compression,
+    encode::{encoded_len, EncodeResult, NbtWriter},
+    tag::{ParseOptions, Parsed, Tag},
+};
+
 mod bytes;
+pub mod compression;
 pub mod encode;
+mod mutf8;
+pub mod region;
+pub mod snbt;
+pub mod stream;
 pub mod tag;
+
+/// The byte order of numbers and lengths in binary NBT.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flavor {
+    /// Big-endian, used by Java Edition
+    Java,
+    /// Big-endian with no name on the root tag, used by the Java Edition
+    /// network protocol since 1.20.2
+    JavaNetwork,
+    /// Little-endian, used by Bedrock Edition files
+    Bedrock,
+    /// Little-endian with varint ints, longs and lengths, used by the Bedrock
+    /// Edition network protocol
+    BedrockNetwork,
+}
+
+impl Flavor {
+    pub fn is_little_endian(self) -> bool {
+        matches!(self, Self::Bedrock | Self::BedrockNetwork)
+    }
+
+    /// Whether strings are stored in Java's modified UTF-8 rather than in
+    /// standard UTF-8.
+    pub fn has_modified_utf8(self) -> bool {
+        matches!(self, Self::Java | Self::JavaNetwork)
+    }
+
+    /// Whether the root tag has a name after its ID.
+    pub fn has_root_name(self) -> bool {
+        self != Self::JavaNetwork
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    Compression(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not read file: {e}"),
+            Self::Compression(e) => write!(f, "Could not decompress: {e}"),
+            Self::Parse(e) => write!(f, "Could not parse tag: {e}"),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) | Self::Compression(e) => Some(e),
+            Self::Parse(e) => Some(e),
+        }
+    }
+}
+
+/// How a tag is stored in a binary NBT file.
+#[derive(Clone, Copy, Debug)]
+pub struct Encoding {
+    pub compression: Compression,
+    pub flavor: Flavor,
+    /// Storage version from the 8-byte header of a Bedrock level.dat, which
+    /// is followed by the length of the rest of the file
+    pub header: Option<u32>,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self {
+            compression: Compression::Gzip,
+            flavor: Flavor::Java,
+            header: None,
+        }
+    }
+}
+
+/// Reads the Bedrock level.dat header, which is only considered present if
+/// its length matches the rest of the file.
+fn read_header(bytes: &[u8]) -> Option<u32> {
+    let version = u32::from_le_bytes(bytes.get(0..4)?.try_into().unwrap());
+    let len = u32::from_le_bytes(bytes.get(4..8)?.try_into().unwrap());
+    (len as usize == bytes.len() - 8).then_some(version)
+}
+
+/// Reads the level.dat header from the start of a decompressed file. Only
+/// as much of the file as the header claims to hold is read to check its
+/// length, so a file without a header is read in full at most once.
+fn stream_header(reader: &mut impl Read, max_size: usize) -> io::Result<Option<u32>> {
+    let mut header = vec![];
+    reader.take(8).read_to_end(&mut header)?;
+    if header.len() < 8 {
+        return Ok(None);
+    }
+    let version = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let len = u64::from(u32::from_le_bytes(header[4..8].try_into().unwrap()));
+    if len > max_size as u64 {
+        return Ok(None);
+    }
+    let rest = io::copy(&mut reader.take(len + 1), &mut io::sink())?;
+    Ok((rest == len).then_some(version))
+}
+
+/// Rewinds a file and wraps it so that it's decompressed as it's read.
+fn decompressed<R: Read + Seek>(
+    file: &mut R,
+    compression: Compression,
+) -> io::Result<Box<dyn Read + '_>> {
+    file.rewind()?;
+    Ok(compression.reader(file))
+}
+
+/// Splits off the level.dat header, which is only looked for if the flavor
+/// is unknown or Bedrock.
+fn split_header(bytes: &[u8], flavor: Option<Flavor>) -> (Option<u32>, &[u8]) {
+    let header = if matches!(flavor, None | Some(Flavor::Bedrock)) {
+        read_header(bytes)
+    } else {
+        None
+    };
+    (header, if header.is_some() { &bytes[8..] } else { bytes })
+}
+
+impl Encoding {
+    /// Detects the encoding of a file and decodes it as it's read, without
+    /// holding the whole file in memory. The compression is sniffed from its
+    /// magic bytes. If `flavor` is not given, files with a level.dat header
+    /// are read as Bedrock, and other files are read as Java, falling back to
+    /// Bedrock and then to a nameless Java root if that fails. Bedrock network
+    /// NBT is never detected.
+    pub fn detect<R: Read + Seek>(
+        mut file: R,
+        flavor: Option<Flavor>,
+        options: ParseOptions,
+    ) -> Result<(Self, Parsed), DecodeError> {
+        let mut magic = vec![];
+        file.by_ref()
+            .take(2)
+            .read_to_end(&mut magic)
+            .map_err(DecodeError::Io)?;
+        let compression = Compression::detect(&magic);
+
+        let header = if matches!(flavor, None | Some(Flavor::Bedrock)) {
+            let mut reader = decompressed(&mut file, compression).map_err(DecodeError::Io)?;
+            stream_header(&mut reader, options.max_size).map_err(DecodeError::Compression)?
+        } else {
+            None
+        };
+        let flavors = match (flavor, header) {
+            (Some(flavor), _) => vec![flavor],
+            (None, Some(_)) => vec![Flavor::Bedrock],
+            (None, None) => vec![Flavor::Java, Flavor::Bedrock, Flavor::JavaNetwork],
+        };
+
+        let mut error = None;
+        for (i, flavor) in flavors.into_iter().enumerate() {
+            let mut reader = decompressed(&mut file, compression).map_err(DecodeError::Io)?;
+            if header.is_some() {
+                reader.read_exact(&mut [0; 8]).map_err(DecodeError::Io)?;
+            }
+            match Tag::parse(&mut reader, flavor, options) {
+                // A fallback flavor may read part of a damaged file as a
+                // smaller tree, so it has to account for the whole file
+                Ok(_) if i > 0 && !matches!(reader.read(&mut [0]), Ok(0)) => (),
+                Ok(parsed) => {
+                    let encoding = Self {
+                        compression,
+                        flavor,
+                        header,
+                    };
+                    return Ok((encoding, parsed));
+                }
+                // Report the error from the most likely flavor
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(DecodeError::Parse(error.unwrap()))
+    }
+
+    /// Like `detect`, but for files that may be damaged. If the file cannot
+    /// be read in full, as much of it as possible is decompressed and parsed,
+    /// and the error where the damage starts is returned with the recovered
+    /// tree. Since damage cannot be told apart from a wrong flavor, the
+    /// recovered tree is read as Java unless the flavor is given or there is a
+    /// level.dat header.
+    pub fn detect_lenient(
+        bytes: &[u8],
+        flavor: Option<Flavor>,
+        options: ParseOptions,
+    ) -> Result<(Self, Parsed, Option<DecodeError>), DecodeError> {
+        let error = match Self::detect(Cursor::new(bytes), flavor, options) {
+            Ok((encoding, parsed)) => return Ok((encoding, parsed, None)),
+            Err(e) => e,
+        };
+
+        let compression = Compression::detect(bytes);
+        let (bytes, compression_error) = compression.decompress_partial(bytes, options.max_size);
+        let (header, body) = split_header(&bytes, flavor);
+        let flavor = match (flavor, header) {
+            (Some(flavor), _) => flavor,
+            (None, Some(_)) => Flavor::Bedrock,
+            (None, None) => Flavor::Java,
+        };
+
+        let (parsed, damage) = Tag::parse_lenient(body, flavor, options).map_err(|_| error)?;
+        let encoding = Self {
+            compression,
+            flavor,
+            header,
+        };
+        // A parse error also tells where in the tree the damage starts
+        let damage = damage
+            .map(DecodeError::Parse)
+            .or_else(|| compression_error.map(DecodeError::Compression));
+        Ok((encoding, parsed, damage))
+    }
+
+    /// Writes a tag, adding the level.dat header if there is one, and
+    /// returns the writer. Tags nested deeper than `max_depth` are refused.
+    pub fn write<W: Write>(&self, writer: W, tag: &Tag, max_depth: usize) -> EncodeResult<W> {
+        let mut compressor = self.compression.writer(writer);
+        if let Some(version) = self.header {
+            let len = encoded_len(tag, self.flavor, max_depth)? as u32;
+            compressor.write_all(&version.to_le_bytes())?;
+            compressor.write_all(&len.to_le_bytes())?;
+        }
+        NbtWriter::new(&mut compressor, self.flavor, max_depth).write_tag(tag)?;
+        Ok(compressor.finish()?)
+    }
+
+    /// Encodes a tag into a new vector.
+    pub fn encode(&self, tag: &Tag, max_depth: usize) -> EncodeResult<Vec<u8>> {
+        self.write(vec![], tag, max_depth)
+    }
+}
@@ -0,0 +1,101 @@
+use std::borrow::Cow;
+
+/// Decodes Java's modified UTF-8, where the null character is stored as the
+/// two bytes `C0 80` and characters outside the Basic Multilingual Plane are
+/// stored as a surrogate pair of 3-byte sequences. Standard UTF-8, including
+/// its 4-byte sequences, is accepted too. Returns `None` if the bytes are not
+/// valid, or hold a surrogate that is not part of a pair.
+pub fn decode(bytes: Vec<u8>) -> Option<String> {
+    // Most strings don't need any special handling
+    let bytes = match String::from_utf8(bytes) {
+        Ok(s) => return Some(s),
+        Err(e) => e.into_bytes(),
+    };
+
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.into_iter();
+    while let Some(byte) = iter.next() {
+        let mut next = || {
+            iter.next()
+                .filter(|b| b & 0xc0 == 0x80)
+                .map(|b| u32::from(b & 0x3f))
+        };
+        let unit = match byte {
+            0x00..=0x7f => u32::from(byte),
+            0xc0..=0xdf => u32::from(byte & 0x1f) << 6 | next()?,
+            0xe0..=0xef => u32::from(byte & 0x0f) << 12 | next()? << 6 | next()?,
+            0xf0..=0xf7 => {
+                let c = u32::from(byte & 0x07) << 18 | next()? << 12 | next()? << 6 | next()?;
+                units.extend_from_slice(char::from_u32(c)?.encode_utf16(&mut [0; 2]));
+                continue;
+            }
+            _ => return None,
+        };
+        units.push(unit as u16);
+    }
+    String::from_utf16(&units).ok()
+}
+
+/// Encodes a string in Java's modified UTF-8. The bytes of the string are
+/// used as they are if it has no null or supplementary characters.
+pub fn encode(s: &str) -> Cow<'_, [u8]> {
+    // Only null and 4-byte sequences are encoded differently
+    if !s.bytes().any(|b| b == 0 || b >= 0xf0) {
+        return Cow::Borrowed(s.as_bytes());
+    }
+
+    let mut bytes = Vec::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '\0' => bytes.extend_from_slice(&[0xc0, 0x80]),
+            c if c.len_utf16() == 2 => {
+                for &mut unit in c.encode_utf16(&mut [0; 2]) {
+                    bytes.extend_from_slice(&[
+                        0xe0 | (unit >> 12) as u8,
+                        0x80 | (unit >> 6 & 0x3f) as u8,
+                        0x80 | (unit & 0x3f) as u8,
+                    ]);
+                }
+            }
+            c => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emoji_is_a_surrogate_pair() {
+        let bytes = encode("a\u{1f600}b");
+        assert_eq!(*bytes, [b'a', 0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80, b'b']);
+        assert_eq!(decode(bytes.into_owned()).unwrap(), "a\u{1f600}b");
+    }
+
+    #[test]
+    fn null_is_two_bytes() {
+        let bytes = encode("a\0b");
+        assert_eq!(*bytes, [b'a', 0xc0, 0x80, b'b']);
+        assert_eq!(decode(bytes.into_owned()).unwrap(), "a\0b");
+    }
+
+    #[test]
+    fn plain_strings_are_borrowed() {
+        assert!(matches!(encode("héllo"), Cow::Borrowed(b) if b == "héllo".as_bytes()));
+    }
+
+    #[test]
+    fn standard_utf8_is_accepted() {
+        assert_eq!(
+            decode("\u{1f600}\0".as_bytes().to_vec()).unwrap(),
+            "\u{1f600}\0"
+        );
+    }
+
+    #[test]
+    fn lone_surrogate_is_rejected() {
+        assert_eq!(decode(vec![0xed, 0xa0, 0xbd, b'a']), None);
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    collections::BTreeSet,
+    fmt, io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    bytes::ParseError,
+    compression::Compression,
+    encode::{EncodeError, NbtWriter},
+    stream::{Event, EventReader},
+    tag::{
+        compound::Compound, id::TagID, payload::TagPayload, traversal::TagTraversal, ParseOptions,
+        Parsed, Tag,
+    },
+    Flavor,
+};
+
+const SECTOR: usize = 4096;
+const CHUNKS: usize = 32 * 32;
+
+#[derive(Debug)]
+pub enum RegionError {
+    InvalidHeader(usize),
+    InvalidChunk(usize, usize),
+    ChunkInHeader(usize, usize),
+    MissingChunk(usize, usize),
+    ChunkTooLarge(usize, usize),
+    UnsupportedCompression(usize, usize, u8),
+    ExternalChunk(usize, usize),
+    Compression(io::Error),
+    Parse(ParseError),
+    Encode(EncodeError),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader(len) => write!(f, "Region file too short ({len} bytes)"),
+            Self::InvalidChunk(x, z) => write!(f, "Chunk {x},{z} points outside the file"),
+            Self::ChunkInHeader(x, z) => write!(f, "Chunk {x},{z} points into the header"),
+            Self::MissingChunk(x, z) => write!(f, "Chunk {x},{z} is not present"),
+            Self::ChunkTooLarge(x, z) => write!(f, "Chunk {x},{z} is larger than 1 MiB"),
+            Self::UnsupportedCompression(x, z, c) => {
+                write!(f, "Chunk {x},{z} has unsupported compression type {c}")
+            }
+            Self::ExternalChunk(x, z) => {
+                write!(f, "Chunk {x},{z} is stored in a separate .mcc file")
+            }
+            Self::Compression(e) => write!(f, "Compression error: {e}"),
+            Self::Parse(e) => write!(f, "Parse error: {e}"),
+            Self::Encode(e) => write!(f, "Encode error: {e}"),
+        }
+    }
+}
+
+pub type RegionResult<T> = Result<T, RegionError>;
+
+/// A chunk that is present in a region file.
+pub struct ChunkInfo {
+    pub x: usize,
+    pub z: usize,
+    /// Last modification time, in seconds since the Unix epoch
+    pub timestamp: u32,
+    /// Offset of the chunk in the file, in sectors
+    pub offset: usize,
+    /// Number of sectors allocated to the chunk
+    pub sectors: usize,
+}
+
+/// The tree built from a region file by `Region::to_tag`.
+pub struct RegionTree {
+    pub tag: Tag,
+    /// Paths of duplicate tags in the chunks
+    pub duplicates: Vec<Vec<TagTraversal>>,
+    /// Chunks that could not be read, which are left out of the tree and kept
+    /// as they are in the file
+    pub errors: Vec<RegionError>,
+}
+
+/// An Anvil (.mca) or `McRegion` (.mcr) file. It starts with a table of the
+/// sector offset and count of each of the 32x32 chunks, followed by a table of
+/// their timestamps, and each chunk is stored in its own run of 4 KiB sectors.
+pub struct Region {
+    bytes: Vec<u8>,
+    options: ParseOptions,
+    // chunks that were changed in the tree since they were last written
+    changed: BTreeSet<(usize, usize)>,
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+fn write_u32(bytes: &mut [u8], pos: usize, n: u32) {
+    bytes[pos..pos + 4].copy_from_slice(&n.to_be_bytes());
+}
+
+/// The compression type byte that precedes the data of a chunk. Its high bit
+/// is set if the chunk is stored in a separate file instead.
+fn compression_from_byte(byte: u8) -> Option<Compression> {
+    match byte {
+        1 => Some(Compression::Gzip),
+        2 => Some(Compression::Zlib),
+        3 => Some(Compression::None),
+        _ => None,
+    }
+}
+
+fn compression_to_byte(compression: Compression) -> u8 {
+    match compression {
+        Compression::Gzip => 1,
+        Compression::Zlib => 2,
+        Compression::None => 3,
+    }
+}
+
+/// Gets the name a chunk is given at the top level of the tree.
+fn chunk_name(x: usize, z: usize) -> String {
+    format!("{x},{z}")
+}
+
+/// The reverse of `chunk_name`.
+fn parse_chunk_name(name: &str) -> Option<(usize, usize)> {
+    name.split_once(',')
+        .and_then(|(x, z)| Some((x.parse().ok()?, z.parse().ok()?)))
+        .filter(|&(x, z)| x < 32 && z < 32)
+}
+
+impl Region {
+    /// Reads the chunk tables of a region file. Chunks are parsed when they
+    /// are read, with `options`.
+    pub fn new(bytes: Vec<u8>, options: ParseOptions) -> RegionResult<Self> {
+        if bytes.len() < 2 * SECTOR {
+            Err(RegionError::InvalidHeader(bytes.len()))
+        } else {
+            Ok(Self {
+                bytes,
+                options,
+                changed: BTreeSet::new(),
+            })
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Gets the sector offset and sector count of a chunk, which are both 0 if
+    /// the chunk is not present.
+    fn location(&self, idx: usize) -> (usize, usize) {
+        let loc = read_u32(&self.bytes, idx * 4);
+        ((loc >> 8) as usize, (loc & 0xff) as usize)
+    }
+
+    fn set_location(&mut self, idx: usize, offset: usize, sectors: usize) {
+        write_u32(&mut self.bytes, idx * 4, (offset << 8 | sectors) as u32);
+    }
+
+    fn timestamp(&self, idx: usize) -> u32 {
+        read_u32(&self.bytes, SECTOR + idx * 4)
+    }
+
+    /// Lists the chunks that are present, in the order of the location table.
+    pub fn chunks(&self) -> Vec<ChunkInfo> {
+        (0..CHUNKS)
+            .filter_map(|idx| {
+                let (offset, sectors) = self.location(idx);
+                (offset != 0).then(|| ChunkInfo {
+                    x: idx % 32,
+                    z: idx / 32,
+                    timestamp: self.timestamp(idx),
+                    offset,
+                    sectors,
+                })
+            })
+            .collect()
+    }
+
+    /// Gets the compression and compressed data of a chunk.
+    fn chunk_data(&self, x: usize, z: usize) -> RegionResult<(Compression, &[u8])> {
+        let (offset, sectors) = self.location(x + z * 32);
+        if offset == 0 {
+            return Err(RegionError::MissingChunk(x, z));
+        }
+        // The first two sectors hold the tables
+        if offset < 2 {
+            return Err(RegionError::ChunkInHeader(x, z));
+        }
+
+        let start = offset * SECTOR;
+        if start + 5 > self.bytes.len() {
+            return Err(RegionError::InvalidChunk(x, z));
+        }
+        // The length includes the compression type byte
+        let len = read_u32(&self.bytes, start) as usize;
+        if len == 0 || len + 4 > sectors * SECTOR || start + 4 + len > self.bytes.len() {
+            return Err(RegionError::InvalidChunk(x, z));
+        }
+
+        let compression_byte = self.bytes[start + 4];
+        if compression_byte & 0x80 != 0 {
+            return Err(RegionError::ExternalChunk(x, z));
+        }
+        let compression = compression_from_byte(compression_byte)
+            .ok_or(RegionError::UnsupportedCompression(x, z, compression_byte))?;
+        Ok((compression, &self.bytes[start + 5..start + 4 + len]))
+    }
+
+    /// Parses a chunk, decompressing it as it's read.
+    pub fn read_chunk(&self, x: usize, z: usize) -> RegionResult<Parsed> {
+        let (compression, data) = self.chunk_data(x, z)?;
+        Tag::parse(compression.reader(data), Flavor::Java, self.options).map_err(RegionError::Parse)
+    }
+
+    /// Reads the name of the root tag of a chunk, which is not shown in the
+    /// tree, without parsing the rest of it.
+    fn root_name(&self, x: usize, z: usize) -> Option<String> {
+        let (compression, data) = self.chunk_data(x, z).ok()?;
+        let mut reader = EventReader::new(compression.reader(data), Flavor::Java, self.options);
+        match reader.next_event() {
+            Ok(Some(Event::Tag(_, name))) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Finds the first run of free sectors that is long enough, which may
+    /// extend past the end of the file.
+    fn allocate(&self, sectors: usize) -> usize {
+        let total = self.bytes.len().div_ceil(SECTOR);
+        let mut used = vec![false; total];
+        used[0] = true;
+        used[1] = true;
+        for idx in 0..CHUNKS {
+            let (offset, count) = self.location(idx);
+            let end = (offset + count).min(total);
+            if offset < end {
+                used[offset..end].fill(true);
+            }
+        }
+
+        // A run starting at the end of the file is always free
+        (2..=total)
+            .find(|&start| (start..start + sectors).all(|s| s >= total || !used[s]))
+            .unwrap()
+    }
+
+    /// Compresses and writes a chunk with the given root name and payload,
+    /// keeping its previous compression if it was present. The chunk is
+    /// written in place if it still fits in its sectors, otherwise it's moved
+    /// to the first free run that's big enough.
+    pub fn write_chunk(
+        &mut self,
+        x: usize,
+        z: usize,
+        name: &str,
+        payload: &TagPayload,
+    ) -> RegionResult<()> {
+        let idx = x + z * 32;
+        let compression = self
+            .chunk_data(x, z)
+            .map_or(Compression::Zlib, |(compression, _)| compression);
+        let mut compressor = compression.writer(vec![]);
+        NbtWriter::new(&mut compressor, Flavor::Java, self.options.max_depth)
+            .write_root(name, payload)
+            .map_err(RegionError::Encode)?;
+        let data = compressor.finish().map_err(RegionError::Compression)?;
+
+        let sectors = (data.len() + 5).div_ceil(SECTOR);
+        if sectors > 0xff {
+            return Err(RegionError::ChunkTooLarge(x, z));
+        }
+
+        let (old_offset, old_sectors) = self.location(idx);
+        let offset = if old_offset != 0 && sectors <= old_sectors {
+            old_offset
+        } else {
+            // Free the old sectors so they can be reused
+            self.set_location(idx, 0, 0);
+            self.allocate(sectors)
+        };
+
+        let start = offset * SECTOR;
+        let end = start + sectors * SECTOR;
+        if self.bytes.len() < end {
+            self.bytes.resize(end, 0);
+        }
+        write_u32(&mut self.bytes, start, (data.len() + 1) as u32);
+        self.bytes[start + 4] = compression_to_byte(compression);
+        self.bytes[start + 5..start + 5 + data.len()].copy_from_slice(&data);
+        self.bytes[start + 5 + data.len()..end].fill(0);
+
+        self.set_location(idx, offset, sectors);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        write_u32(&mut self.bytes, SECTOR + idx * 4, now as u32);
+        Ok(())
+    }
+
+    /// Builds a tree with every present chunk as a compound at the top level,
+    /// named by its coordinates within the region. Chunks that can't be read
+    /// are left out, and reported along with the paths of duplicate tags.
+    pub fn to_tag(&self) -> RegionTree {
+        let mut tags = Compound::new();
+        let mut duplicates = vec![];
+        let mut errors = vec![];
+        for chunk in self.chunks() {
+            let parsed = match self.read_chunk(chunk.x, chunk.z) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            let name = chunk_name(chunk.x, chunk.z);
+            duplicates.extend(parsed.duplicates.into_iter().map(|path| {
+                let mut chunk_path = vec![TagTraversal::Compound(name.clone())];
+                chunk_path.extend(path);
+                chunk_path
+            }));
+
+            let payload = parsed.tag.payload;
+            tags.insert(Tag {
+                id: (&payload).into(),
+                name,
+                payload,
+            });
+        }
+
+        RegionTree {
+            tag: Tag {
+                id: TagID::Compound,
+                name: String::new(),
+                payload: TagPayload::Compound(tags),
+            },
+            duplicates,
+            errors,
+        }
+    }
+
+    /// Records that a chunk of a tree built by `to_tag` was changed, given its
+    /// name at the top level.
+    pub fn mark_changed(&mut self, name: &str) {
+        if let Some(chunk) = parse_chunk_name(name) {
+            self.changed.insert(chunk);
+        }
+    }
+
+    /// Writes back every chunk of a tree built by `to_tag` that was marked as
+    /// changed.
+    pub fn update(&mut self, tag: &Tag) -> RegionResult<()> {
+        let tags = tag.payload.as_compound().unwrap();
+        while let Some(&(x, z)) = self.changed.first() {
+            if let Some(chunk) = tags.get(&chunk_name(x, z)) {
+                // The root name of a chunk is not shown in the tree, so keep
+                // the original one
+                let name = self.root_name(x, z).unwrap_or_default();
+                self.write_chunk(x, z, &name, &chunk.payload)?;
+            }
+            self.changed.remove(&(x, z));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_region() -> Region {
+        Region::new(vec![0; 2 * SECTOR], ParseOptions::default()).unwrap()
+    }
+
+    fn compound(name: &str, payload: TagPayload) -> TagPayload {
+        let mut tags = Compound::new();
+        tags.insert(Tag {
+            id: (&payload).into(),
+            name: name.to_string(),
+            payload,
+        });
+        TagPayload::Compound(tags)
+    }
+
+    /// Bytes that zlib can't shrink, so that the chunk takes up about as many
+    /// sectors as it has bytes.
+    fn noise(len: usize) -> Vec<i8> {
+        let mut state = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state.to_be_bytes()[0] as i8
+            })
+            .collect()
+    }
+
+    fn location(region: &Region, x: usize, z: usize) -> (usize, usize) {
+        region.location(x + z * 32)
+    }
+
+    #[test]
+    fn grown_chunk_is_moved() {
+        let mut region = empty_region();
+        let small = compound("a", TagPayload::Int(1));
+        region.write_chunk(0, 0, "", &small).unwrap();
+        region.write_chunk(1, 0, "", &small).unwrap();
+        assert_eq!(location(&region, 0, 0), (2, 1));
+        assert_eq!(location(&region, 1, 0), (3, 1));
+
+        let big = compound("data", TagPayload::ByteArray(noise(2 * SECTOR)));
+        region.write_chunk(0, 0, "root", &big).unwrap();
+        assert_eq!(location(&region, 0, 0), (4, 3));
+        assert_eq!(region.as_bytes().len(), 7 * SECTOR);
+        let parsed = region.read_chunk(0, 0).unwrap();
+        assert_eq!(parsed.tag.name, "root");
+        assert_eq!(parsed.tag.payload, big);
+        assert_eq!(region.read_chunk(1, 0).unwrap().tag.payload, small);
+
+        // The sector the chunk moved out of is free again
+        region.write_chunk(2, 0, "", &small).unwrap();
+        assert_eq!(location(&region, 2, 0), (2, 1));
+
+        // A chunk that shrinks stays where it is, and gives up the sectors it
+        // no longer needs
+        region.write_chunk(0, 0, "", &small).unwrap();
+        assert_eq!(location(&region, 0, 0), (4, 1));
+        assert_eq!(region.read_chunk(0, 0).unwrap().tag.payload, small);
+    }
+
+    #[test]
+    fn update_writes_changed_chunks() {
+        let mut region = empty_region();
+        region
+            .write_chunk(0, 0, "level", &compound("a", TagPayload::Int(1)))
+            .unwrap();
+        region
+            .write_chunk(5, 3, "", &compound("b", TagPayload::Int(2)))
+            .unwrap();
+        let untouched = region.as_bytes()[3 * SECTOR..4 * SECTOR].to_vec();
+
+        let mut tree = region.to_tag();
+        assert!(tree.errors.is_empty());
+        let chunks = tree.tag.payload.as_compound_mut().unwrap();
+        *chunks.get_mut("0,0").unwrap() = compound("a", TagPayload::Int(7));
+        *chunks.get_mut("5,3").unwrap() = compound("b", TagPayload::Int(8));
+        region.mark_changed("0,0");
+        region.update(&tree.tag).unwrap();
+
+        let parsed = region.read_chunk(0, 0).unwrap();
+        assert_eq!(parsed.tag.name, "level");
+        assert_eq!(parsed.tag.payload, compound("a", TagPayload::Int(7)));
+        // Chunks that weren't marked are left as they are in the file
+        assert_eq!(&region.as_bytes()[3 * SECTOR..4 * SECTOR], untouched);
+    }
+}
@@ -0,0 +1,804 @@
+use std::fmt::{self, Write};
+
+use super::tag::{
+    compound::{Compound, CompoundError},
+    id::TagID,
+    list::List,
+    payload::TagPayload,
+    traversal::TagTraversal,
+    ParseOptions, Parsed, Tag,
+};
+
+/// Displays a payload as full, unabbreviated SNBT, in the same form as
+/// Minecraft's `/data get` output. If `indent` is set, containers are
+/// pretty-printed with that many spaces per level, otherwise the output is on
+/// a single line.
+pub struct Snbt<'a> {
+    payload: &'a TagPayload,
+    indent: Option<usize>,
+}
+
+impl<'a> Snbt<'a> {
+    pub fn new(payload: &'a TagPayload, indent: Option<usize>) -> Self {
+        Self { payload, indent }
+    }
+
+    fn newline(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        if let Some(indent) = self.indent {
+            f.write_char('\n')?;
+            for _ in 0..indent * depth {
+                f.write_char(' ')?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the items of a list or compound between `open` and `close`,
+    /// putting each of them on its own line when pretty-printing.
+    fn write_container<T>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        depth: usize,
+        (open, close): (char, char),
+        items: &[T],
+        write_item: impl Fn(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+    ) -> fmt::Result {
+        f.write_char(open)?;
+        if !items.is_empty() {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    f.write_char(',')?;
+                }
+                self.newline(f, depth + 1)?;
+                write_item(f, item)?;
+            }
+            self.newline(f, depth)?;
+        }
+        f.write_char(close)
+    }
+
+    /// Writes a typed array such as `[I;1,2,3]` on a single line, with
+    /// `suffix` after each number.
+    fn write_array<T: fmt::Display>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        prefix: char,
+        suffix: &str,
+        values: &[T],
+    ) -> fmt::Result {
+        write!(f, "[{prefix};")?;
+        if self.indent.is_some() && !values.is_empty() {
+            f.write_char(' ')?;
+        }
+        for (i, n) in values.iter().enumerate() {
+            if i > 0 {
+                f.write_str(self.separator())?;
+            }
+            write!(f, "{n}{suffix}")?;
+        }
+        f.write_char(']')
+    }
+
+    fn separator(&self) -> &'static str {
+        if self.indent.is_some() {
+            ", "
+        } else {
+            ","
+        }
+    }
+
+    fn write_payload(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        payload: &TagPayload,
+        depth: usize,
+    ) -> fmt::Result {
+        match payload {
+            TagPayload::End => Ok(()),
+            TagPayload::Byte(n) => write!(f, "{n}b"),
+            TagPayload::Short(n) => write!(f, "{n}s"),
+            TagPayload::Int(n) => write!(f, "{n}"),
+            TagPayload::Long(n) => write!(f, "{n}L"),
+            // SNBT has no NaN or infinities, so NaN is written as 0 and
+            // infinities as the largest finite number of their sign
+            TagPayload::Float(n) => {
+                let n = if n.is_nan() {
+                    0.0
+                } else {
+                    n.clamp(f32::MIN, f32::MAX)
+                };
+                write_float(f, n, 'f')
+            }
+            TagPayload::Double(n) => {
+                let n = if n.is_nan() {
+                    0.0
+                } else {
+                    n.clamp(f64::MIN, f64::MAX)
+                };
+                write_float(f, n, 'd')
+            }
+            TagPayload::String(s) => f.write_str(&quote(s)),
+            TagPayload::ByteArray(v) => self.write_array(f, 'B', "b", v),
+            TagPayload::IntArray(v) => self.write_array(f, 'I', "", v),
+            TagPayload::LongArray(v) => self.write_array(f, 'L', "L", v),
+            TagPayload::List(list) => {
+                self.write_container(f, depth, ('[', ']'), list.as_slice(), |f, p| {
+                    self.write_payload(f, p, depth + 1)
+                })
+            }
+            TagPayload::Compound(tags) => {
+                self.write_container(f, depth, ('{', '}'), tags.as_slice(), |f, t| {
+                    f.write_str(&quote_key(&t.name))?;
+                    f.write_str(if self.indent.is_some() { ": " } else { ":" })?;
+                    self.write_payload(f, &t.payload, depth + 1)
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Snbt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_payload(f, self.payload, 0)
+    }
+}
+
+/// Writes a finite float the way Java's `toString` does, which is how `/data
+/// get` shows it: in exponent form such as `1.0E308` outside of 10^-3 to
+/// 10^7, and always with a decimal point.
+fn write_float<T>(f: &mut fmt::Formatter<'_>, n: T, suffix: char) -> fmt::Result
+where
+    T: fmt::Display + fmt::LowerExp + Into<f64> + Copy,
+{
+    let abs = n.into().abs();
+    let s = if abs > 0.0 && !(1e-3..1e7).contains(&abs) {
+        format!("{n:e}")
+    } else {
+        n.to_string()
+    };
+    let (mantissa, exponent) = s.split_once('e').unwrap_or((&s, ""));
+    f.write_str(mantissa)?;
+    if !mantissa.contains('.') {
+        f.write_str(".0")?;
+    }
+    if !exponent.is_empty() {
+        write!(f, "E{exponent}")?;
+    }
+    f.write_char(suffix)
+}
+
+/// Counts the NaN and infinite floats in a payload, which SNBT has no way to
+/// write, so `Snbt` writes them as finite numbers instead.
+pub fn count_non_finite(payload: &TagPayload) -> usize {
+    match payload {
+        TagPayload::Float(n) => usize::from(!n.is_finite()),
+        TagPayload::Double(n) => usize::from(!n.is_finite()),
+        TagPayload::List(list) => list.as_slice().iter().map(count_non_finite).sum(),
+        TagPayload::Compound(tags) => tags.iter().map(|t| count_non_finite(&t.payload)).sum(),
+        _ => 0,
+    }
+}
+
+/// Quotes and escapes a string the way Minecraft does: double quotes are used
+/// unless the string contains a double quote but no single quote. Line breaks,
+/// tabs and nulls are escaped too, so that the string stays on one line.
+fn quote(s: &str) -> String {
+    let quote = if s.contains('"') && !s.contains('\'') {
+        '\''
+    } else {
+        '"'
+    };
+
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push(quote);
+    for ch in s.chars() {
+        match ch {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            _ => {
+                if ch == quote || ch == '\\' {
+                    quoted.push('\\');
+                }
+                quoted.push(ch);
+            }
+        }
+    }
+    quoted.push(quote);
+    quoted
+}
+
+/// Characters that can appear in a compound key without quoting it.
+fn is_unquoted_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-' | '.' | '+')
+}
+
+/// Leaves a compound key unquoted if possible, otherwise quotes it.
+fn quote_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(is_unquoted_char) {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+/// Converts the payload of a tag into SNBT. The name of the root tag isn't part
+/// of SNBT and is left out.
+pub fn to_snbt(tag: &Tag, indent: Option<usize>) -> String {
+    Snbt::new(&tag.payload, indent).to_string()
+}
+
+#[derive(Debug)]
+pub enum SnbtError {
+    Expected(usize, &'static str),
+    InvalidEscape(usize, char),
+    NumberOutOfRange(usize, String),
+    MixedList(usize, TagID, TagID),
+    InvalidArrayElement(usize, TagID),
+    TrailingData(usize),
+    TooDeep(usize, usize),
+    DuplicateTag(usize, String),
+}
+
+impl fmt::Display for SnbtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Expected(pos, what) => write!(f, "Expected {what} at position {pos}"),
+            Self::InvalidEscape(pos, ch) => write!(f, "Invalid escape '\\{ch}' at position {pos}"),
+            Self::NumberOutOfRange(pos, n) => {
+                write!(f, "Number {n} out of range at position {pos}")
+            }
+            Self::MixedList(pos, expected, found) => write!(
+                f,
+                "Expected {expected} in list but found {found} at position {pos}"
+            ),
+            Self::InvalidArrayElement(pos, id) => {
+                write!(f, "Invalid element for {id} at position {pos}")
+            }
+            Self::TrailingData(pos) => write!(f, "Unexpected data at position {pos}"),
+            Self::TooDeep(pos, max) => write!(
+                f,
+                "Tags are nested deeper than the limit of {max} at position {pos}"
+            ),
+            Self::DuplicateTag(pos, name) => {
+                write!(f, "Duplicate tag \"{name}\" at position {pos}")
+            }
+        }
+    }
+}
+
+pub type SnbtResult<T> = Result<T, SnbtError>;
+
+/// Whether a string is an optionally signed integer.
+fn is_int(s: &str) -> bool {
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s);
+    !digits.is_empty() && digits.chars().all(|ch| ch.is_ascii_digit())
+}
+
+/// Whether a string is an optionally signed decimal number, with an optional
+/// exponent.
+fn is_float(s: &str) -> bool {
+    s.chars().any(|ch| ch.is_ascii_digit())
+        && s.chars()
+            .all(|ch| ch.is_ascii_digit() || matches!(ch, '.' | 'e' | 'E' | '-' | '+'))
+        && s.parse::<f64>().is_ok()
+}
+
+/// Parses a float, which is out of range if it's too large to be finite.
+fn parse_finite<T: std::str::FromStr + Into<f64> + Copy>(num: &str) -> Option<T> {
+    num.parse().ok().filter(|&n: &T| n.into().is_finite())
+}
+
+/// Turns an unquoted token into a payload. Tokens that look like numbers
+/// become numbers of the type given by their suffix (`Int` or `Double` if
+/// there's none), `true` and `false` become bytes, and anything else is an
+/// unquoted string. Like the output of `Snbt`, numbers are always finite.
+fn parse_token(token: &str, pos: usize) -> SnbtResult<TagPayload> {
+    let out_of_range = || SnbtError::NumberOutOfRange(pos, token.to_string());
+    match token {
+        "true" => return Ok(TagPayload::Byte(1)),
+        "false" => return Ok(TagPayload::Byte(0)),
+        _ => (),
+    }
+
+    let mut chars = token.chars();
+    let suffix = chars.next_back().map(|ch| ch.to_ascii_lowercase());
+    let num = chars.as_str();
+    Ok(match suffix {
+        Some('b') if is_int(num) => TagPayload::Byte(num.parse().map_err(|_| out_of_range())?),
+        Some('s') if is_int(num) => TagPayload::Short(num.parse().map_err(|_| out_of_range())?),
+        Some('l') if is_int(num) => TagPayload::Long(num.parse().map_err(|_| out_of_range())?),
+        Some('f') if is_float(num) => {
+            TagPayload::Float(parse_finite(num).ok_or_else(out_of_range)?)
+        }
+        Some('d') if is_float(num) => {
+            TagPayload::Double(parse_finite(num).ok_or_else(out_of_range)?)
+        }
+        _ if is_int(token) => TagPayload::Int(token.parse().map_err(|_| out_of_range())?),
+        _ if is_float(token) => TagPayload::Double(parse_finite(token).ok_or_else(out_of_range)?),
+        _ => TagPayload::String(token.to_string()),
+    })
+}
+
+/// A recursive descent parser over SNBT. Positions are byte offsets into the
+/// input.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    // number of containers around the current value
+    depth: usize,
+    options: ParseOptions,
+    // path to the current value
+    path: Vec<TagTraversal>,
+    // paths of the tags that were resolved by the duplicate policy
+    duplicates: Vec<Vec<TagTraversal>>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    /// Skips whitespace, then consumes `ch` if it's the next character.
+    fn eat(&mut self, ch: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(ch) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, ch: char, what: &'static str) -> SnbtResult<()> {
+        if self.eat(ch) {
+            Ok(())
+        } else {
+            Err(SnbtError::Expected(self.pos, what))
+        }
+    }
+
+    fn parse_quoted(&mut self) -> SnbtResult<String> {
+        let quote = self.next().unwrap();
+        let mut s = String::new();
+        loop {
+            let pos = self.pos;
+            match self.next() {
+                Some('\\') => s.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some(ch @ ('\\' | '"' | '\'')) => ch,
+                    Some(ch) => return Err(SnbtError::InvalidEscape(pos, ch)),
+                    None => return Err(SnbtError::Expected(self.pos, "an escape sequence")),
+                }),
+                Some(ch) if ch == quote => return Ok(s),
+                Some(ch) => s.push(ch),
+                None => {
+                    return Err(SnbtError::Expected(
+                        self.pos,
+                        if quote == '"' { "'\"'" } else { "'''" },
+                    ))
+                }
+            }
+        }
+    }
+
+    fn parse_unquoted(&mut self) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(is_unquoted_char) {
+            self.next();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn parse_key(&mut self) -> SnbtResult<String> {
+        self.skip_whitespace();
+        if let Some('"' | '\'') = self.peek() {
+            return self.parse_quoted();
+        }
+
+        let pos = self.pos;
+        let key = self.parse_unquoted();
+        if key.is_empty() {
+            Err(SnbtError::Expected(pos, "a key"))
+        } else {
+            Ok(key.to_string())
+        }
+    }
+
+    fn parse_compound(&mut self) -> SnbtResult<TagPayload> {
+        self.next();
+        let mut tags = Compound::new();
+        if !self.eat('}') {
+            loop {
+                self.skip_whitespace();
+                let pos = self.pos;
+                let name = self.parse_key()?;
+                self.expect(':', "':'")?;
+                self.path.push(TagTraversal::Compound(name.clone()));
+                let payload = self.parse_value()?;
+                let tag = Tag {
+                    id: (&payload).into(),
+                    name,
+                    payload,
+                };
+                match tags.insert_with(tag, self.options.duplicates) {
+                    Ok(None) => (),
+                    Ok(Some(_)) => self.duplicates.push(self.path.clone()),
+                    Err(CompoundError::Exists(name)) => {
+                        return Err(SnbtError::DuplicateTag(pos, name))
+                    }
+                    Err(CompoundError::Missing(_)) => unreachable!(),
+                }
+                self.path.pop();
+
+                if self.eat('}') {
+                    break;
+                }
+                self.expect(',', "',' or '}'")?;
+            }
+        }
+
+        Ok(TagPayload::Compound(tags))
+    }
+
+    /// Parses the elements of a list or array, up to and including `]`.
+    fn parse_elements<T>(
+        &mut self,
+        mut convert: impl FnMut(usize, TagPayload) -> SnbtResult<T>,
+    ) -> SnbtResult<Vec<T>> {
+        let mut payloads = vec![];
+        if !self.eat(']') {
+            loop {
+                self.skip_whitespace();
+                let pos = self.pos;
+                self.path.push(TagTraversal::Array(payloads.len() as i32));
+                let payload = self.parse_value()?;
+                self.path.pop();
+                payloads.push(convert(pos, payload)?);
+
+                if self.eat(']') {
+                    break;
+                }
+                self.expect(',', "',' or ']'")?;
+            }
+        }
+        Ok(payloads)
+    }
+
+    fn parse_array(&mut self, id: TagID) -> SnbtResult<TagPayload> {
+        // Skip the type letter and ';'
+        self.next();
+        self.next();
+        let payloads = self.parse_elements(|pos, payload| {
+            let n = match payload {
+                TagPayload::Byte(n) => i64::from(n),
+                TagPayload::Short(n) => i64::from(n),
+                TagPayload::Int(n) => i64::from(n),
+                TagPayload::Long(n) => n,
+                _ => return Err(SnbtError::InvalidArrayElement(pos, id)),
+            };
+            let invalid = |_| SnbtError::InvalidArrayElement(pos, id);
+            Ok(match id {
+                TagID::ByteArray => TagPayload::Byte(n.try_into().map_err(invalid)?),
+                TagID::IntArray => TagPayload::Int(n.try_into().map_err(invalid)?),
+                _ => TagPayload::Long(n),
+            })
+        })?;
+
+        // Every element has been converted to the type of the array
+        Ok(match id {
+            TagID::ByteArray => TagPayload::ByteArray(
+                payloads
+                    .iter()
+                    .filter_map(TagPayload::as_byte)
+                    .copied()
+                    .collect(),
+            ),
+            TagID::IntArray => TagPayload::IntArray(
+                payloads
+                    .iter()
+                    .filter_map(TagPayload::as_int)
+                    .copied()
+                    .collect(),
+            ),
+            _ => TagPayload::LongArray(
+                payloads
+                    .iter()
+                    .filter_map(TagPayload::as_long)
+                    .copied()
+                    .collect(),
+            ),
+        })
+    }
+
+    fn parse_list(&mut self) -> SnbtResult<TagPayload> {
+        self.next();
+        let array_id = match &self.input.as_bytes()[self.pos..] {
+            [b'B', b';', ..] => Some(TagID::ByteArray),
+            [b'I', b';', ..] => Some(TagID::IntArray),
+            [b'L', b';', ..] => Some(TagID::LongArray),
+            _ => None,
+        };
+        if let Some(id) = array_id {
+            return self.parse_array(id);
+        }
+
+        let mut list = List::default();
+        self.parse_elements(|pos, payload| {
+            let id = TagID::from(&payload);
+            list.push(payload)
+                .map_err(|_| SnbtError::MixedList(pos, list.id(), id))
+        })?;
+        Ok(TagPayload::List(list))
+    }
+
+    /// Parses a compound or list, which may not be nested deeper than
+    /// `options.max_depth`.
+    fn parse_container(
+        &mut self,
+        parse: fn(&mut Self) -> SnbtResult<TagPayload>,
+    ) -> SnbtResult<TagPayload> {
+        if self.depth > self.options.max_depth {
+            return Err(SnbtError::TooDeep(self.pos, self.options.max_depth));
+        }
+        self.depth += 1;
+        let payload = parse(self);
+        self.depth -= 1;
+        payload
+    }
+
+    fn parse_value(&mut self) -> SnbtResult<TagPayload> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.parse_container(Self::parse_compound),
+            Some('[') => self.parse_container(Self::parse_list),
+            Some('"' | '\'') => Ok(TagPayload::String(self.parse_quoted()?)),
+            _ => {
+                let pos = self.pos;
+                let token = self.parse_unquoted();
+                if token.is_empty() {
+                    Err(SnbtError::Expected(pos, "a value"))
+                } else {
+                    parse_token(token, pos)
+                }
+            }
+        }
+    }
+}
+
+/// Parses a single SNBT value, which must make up the whole input apart from
+/// surrounding whitespace. Tags with the same name as one before them in
+/// their compound are resolved by `options.duplicates`, and their paths are
+/// returned with the value.
+fn parse(input: &str, options: ParseOptions) -> SnbtResult<(TagPayload, Vec<Vec<TagTraversal>>)> {
+    let mut parser = Parser {
+        input,
+        pos: 0,
+        depth: 0,
+        options,
+        path: vec![],
+        duplicates: vec![],
+    };
+    let payload = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos == input.len() {
+        Ok((payload, parser.duplicates))
+    } else {
+        Err(SnbtError::TrailingData(parser.pos))
+    }
+}
+
+/// Parses a single SNBT value with the default options, where a repeated
+/// name replaces the tag before it.
+pub fn parse_payload(input: &str) -> SnbtResult<TagPayload> {
+    parse(input, ParseOptions::default()).map(|(payload, _)| payload)
+}
+
+/// Parses SNBT into a tag. SNBT has no root name, so the tag's name is empty.
+pub fn parse_snbt(input: &str, options: ParseOptions) -> SnbtResult<Parsed> {
+    let (payload, duplicates) = parse(input, options)?;
+    Ok(Parsed {
+        tag: Tag {
+            id: (&payload).into(),
+            name: String::new(),
+            payload,
+        },
+        len: input.len(),
+        duplicates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbt::tag::{compound::DuplicatePolicy, traversal::fmt_path};
+
+    fn snbt(payload: &TagPayload) -> String {
+        Snbt::new(payload, None).to_string()
+    }
+
+    fn with(options: ParseOptions, input: &str) -> SnbtResult<Parsed> {
+        parse_snbt(input, options)
+    }
+
+    fn duplicates(policy: DuplicatePolicy) -> ParseOptions {
+        ParseOptions {
+            duplicates: policy,
+            ..ParseOptions::default()
+        }
+    }
+
+    const SAMPLE: &str = r#"{byte:-5b,short:300s,int:5,long:1099511627776L,float:0.1f,
+        double:-2.5d,"key with spaces":'it\'s',bytes:[B;1b,-1b],ints:[I;7,-8],
+        longs:[L;-9L],list:[{a:1},{}],nested:{lists:[[1],[]],empty:[]},yes:true}"#;
+
+    #[test]
+    fn round_trip() {
+        let payload = parse_payload(SAMPLE).unwrap();
+        assert_eq!(parse_payload(&snbt(&payload)).unwrap(), payload);
+        let pretty = Snbt::new(&payload, Some(2)).to_string();
+        assert!(pretty.contains("\n  "));
+        assert_eq!(parse_payload(&pretty).unwrap(), payload);
+    }
+
+    #[test]
+    fn values() {
+        let payload = parse_payload(SAMPLE).unwrap();
+        let tags = payload.as_compound().unwrap();
+        let get = |name| &tags.get(name).unwrap().payload;
+        assert_eq!(get("short"), &TagPayload::Short(300));
+        assert_eq!(get("long"), &TagPayload::Long(1 << 40));
+        assert_eq!(
+            get("key with spaces"),
+            &TagPayload::String("it's".to_string())
+        );
+        assert_eq!(get("yes"), &TagPayload::Byte(1));
+        assert_eq!(parse_payload("1.5").unwrap(), TagPayload::Double(1.5));
+        assert_eq!(
+            parse_payload("minecraft.stone").unwrap(),
+            TagPayload::String("minecraft.stone".to_string())
+        );
+        assert!(matches!(
+            parse_payload("128b"),
+            Err(SnbtError::NumberOutOfRange(0, _))
+        ));
+    }
+
+    #[test]
+    fn typed_arrays() {
+        assert_eq!(
+            parse_payload("[B;1b,-2b,3]").unwrap(),
+            TagPayload::ByteArray(vec![1, -2, 3])
+        );
+        assert_eq!(
+            parse_payload("[I; 1, 2s]").unwrap(),
+            TagPayload::IntArray(vec![1, 2])
+        );
+        assert_eq!(
+            parse_payload("[L;1L,2]").unwrap(),
+            TagPayload::LongArray(vec![1, 2])
+        );
+        assert_eq!(parse_payload("[I;]").unwrap(), TagPayload::IntArray(vec![]));
+        assert!(matches!(
+            parse_payload("[B;300]"),
+            Err(SnbtError::InvalidArrayElement(3, TagID::ByteArray))
+        ));
+        assert!(matches!(
+            parse_payload("[I;1,1.5]"),
+            Err(SnbtError::InvalidArrayElement(5, TagID::IntArray))
+        ));
+    }
+
+    #[test]
+    fn lists_hold_one_type() {
+        let list = parse_payload("[1,2,3]").unwrap();
+        assert_eq!(list.as_list().unwrap().id(), TagID::Int);
+        assert!(matches!(
+            parse_payload("[1,2b]"),
+            Err(SnbtError::MixedList(3, TagID::Int, TagID::Byte))
+        ));
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let input = "{a:1,b:{c:2,c:3},a:4}";
+        let parsed = with(duplicates(DuplicatePolicy::Last), input).unwrap();
+        assert_eq!(snbt(&parsed.tag.payload), "{a:4,b:{c:3}}");
+        let paths: Vec<_> = parsed.duplicates.iter().map(|p| fmt_path(p)).collect();
+        assert_eq!(paths, ["b > c", "a"]);
+
+        let parsed = with(duplicates(DuplicatePolicy::First), input).unwrap();
+        assert_eq!(snbt(&parsed.tag.payload), "{a:1,b:{c:2}}");
+        assert_eq!(parsed.duplicates.len(), 2);
+
+        let Err(e) = with(duplicates(DuplicatePolicy::Error), input) else {
+            panic!("duplicate tags were accepted");
+        };
+        assert!(matches!(e, SnbtError::DuplicateTag(12, name) if name == "c"));
+    }
+
+    #[test]
+    fn too_deep() {
+        let options = ParseOptions {
+            max_depth: 2,
+            ..ParseOptions::default()
+        };
+        assert!(with(options, "[[[1]]]").is_ok());
+        assert!(matches!(
+            with(options, "[[{a:[]}]]"),
+            Err(SnbtError::TooDeep(5, 2))
+        ));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(matches!(
+            parse_payload("{a:1"),
+            Err(SnbtError::Expected(4, _))
+        ));
+        assert!(matches!(
+            parse_payload("{a:1} x"),
+            Err(SnbtError::TrailingData(6))
+        ));
+        assert!(matches!(
+            parse_payload(r#""\q""#),
+            Err(SnbtError::InvalidEscape(1, 'q'))
+        ));
+    }
+
+    #[test]
+    fn floats_are_written_like_java() {
+        assert_eq!(snbt(&TagPayload::Double(1e308)), "1.0E308d");
+        assert_eq!(snbt(&TagPayload::Float(1.5e-45)), "1.0E-45f");
+        assert_eq!(snbt(&TagPayload::Double(1e7)), "1.0E7d");
+        assert_eq!(snbt(&TagPayload::Double(9_999_999.5)), "9999999.5d");
+        assert_eq!(snbt(&TagPayload::Double(9e-4)), "9.0E-4d");
+        assert_eq!(snbt(&TagPayload::Float(0.001)), "0.001f");
+        assert_eq!(snbt(&TagPayload::Float(1.0)), "1.0f");
+        assert_eq!(snbt(&TagPayload::Double(-0.0)), "-0.0d");
+    }
+
+    #[test]
+    fn non_finite_floats_are_written_as_finite() {
+        assert_eq!(snbt(&TagPayload::Float(f32::NAN)), "0.0f");
+        assert_eq!(
+            snbt(&TagPayload::Double(f64::INFINITY)),
+            "1.7976931348623157E308d"
+        );
+        assert_eq!(
+            snbt(&TagPayload::Float(f32::NEG_INFINITY)),
+            "-3.4028235E38f"
+        );
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        let s = TagPayload::String("a\0b\nc\td\re\\f".to_string());
+        assert_eq!(snbt(&s), r#""a\0b\nc\td\re\\f""#);
+        assert_eq!(parse_payload(&snbt(&s)).unwrap(), s);
+    }
+
+    #[test]
+    fn quotes_like_minecraft() {
+        let s = TagPayload::String(r#"say "hi""#.to_string());
+        assert_eq!(snbt(&s), r#"'say "hi"'"#);
+        let s = TagPayload::String(r#"it's "hi""#.to_string());
+        assert_eq!(snbt(&s), r#""it's \"hi\"""#);
+    }
+}
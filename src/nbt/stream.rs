@@ -0,0 +1,183 @@
+use std::{io::Read, mem::size_of};
+
+use super::{
+    bytes::{NbtReader, ParseResult},
+    tag::{id::TagID, payload::TagPayload, traversal::TagTraversal, ParseOptions, Tag},
+    Flavor,
+};
+
+/// Memory taken up by a tag of a compound besides its payload and its name,
+/// which is also the key of the compound's index.
+const ENTRY_SIZE: usize = size_of::<Tag>() - size_of::<TagPayload>() + size_of::<(String, usize)>();
+
+/// A piece of an NBT file, in the order it's stored in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A tag in a compound, or the root tag, followed by the events of its
+    /// payload
+    Tag(TagID, String),
+    /// The start of a compound, followed by its tags up to an `End`
+    StartCompound,
+    /// The start of a list with its element type and length, followed by
+    /// the events of each element and an `End`
+    StartList(TagID, usize),
+    /// A payload that is not a compound or a list
+    Value(TagPayload),
+    /// The end of the innermost compound or list
+    End,
+}
+
+/// A compound or list that is being read.
+enum Frame {
+    Compound,
+    List { id: TagID, len: usize, read: usize },
+}
+
+/// Reads an NBT file as a sequence of events, so that it can be scanned
+/// without building the whole tree. Only the containers enclosing the current
+/// tag are kept in memory, but every tag is charged against the memory limit
+/// for what it would take up in a tree, since that's what it's read for.
+pub struct EventReader<R> {
+    bytes: NbtReader<R>,
+    stack: Vec<Frame>,
+    // type of the payload to read next, after a `Tag` event
+    pending: Option<TagID>,
+    started: bool,
+}
+
+impl<R: Read> EventReader<R> {
+    /// Makes a reader that enforces the limits of `options`.
+    pub fn new(reader: R, flavor: Flavor, options: ParseOptions) -> Self {
+        Self {
+            bytes: NbtReader::new(reader, flavor, options),
+            stack: vec![],
+            pending: None,
+            started: false,
+        }
+    }
+
+    /// Gets the number of bytes read so far.
+    pub fn offset(&self) -> usize {
+        self.bytes.offset()
+    }
+
+    /// Gets the path to the tag being read, which is the enclosing container
+    /// between the events of two tags.
+    pub fn path(&self) -> &[TagTraversal] {
+        &self.bytes.path
+    }
+
+    /// Reads the next event, or returns `None` once the root tag has been
+    /// read in full. Nothing more is read after an error.
+    pub fn next_event(&mut self) -> ParseResult<Option<Event>> {
+        let event = self.read_event();
+        if event.is_err() {
+            self.stack.clear();
+            self.pending = None;
+        }
+        event
+    }
+
+    fn read_event(&mut self) -> ParseResult<Option<Event>> {
+        if !self.started {
+            self.started = true;
+            return self.read_root().map(Some);
+        }
+        if let Some(id) = self.pending.take() {
+            return self.read_payload(id).map(Some);
+        }
+
+        match self.stack.last_mut() {
+            None => Ok(None),
+            Some(Frame::Compound) => {
+                self.bytes.expected = TagID::Compound;
+                let id = self.bytes.next_id()?;
+                if id == TagID::End {
+                    self.stack.pop();
+                    self.finish_payload();
+                    return Ok(Some(Event::End));
+                }
+
+                let name = self.bytes.next_str()?;
+                self.bytes.charge(ENTRY_SIZE + name.len())?;
+                self.bytes.path.push(TagTraversal::Compound(name.clone()));
+                self.pending = Some(id);
+                Ok(Some(Event::Tag(id, name)))
+            }
+            Some(Frame::List { id, len, read }) => {
+                if read == len {
+                    self.stack.pop();
+                    self.finish_payload();
+                    return Ok(Some(Event::End));
+                }
+
+                let id = *id;
+                self.bytes.path.push(TagTraversal::Array(*read as i32));
+                *read += 1;
+                self.read_payload(id).map(Some)
+            }
+        }
+    }
+
+    /// Reads the ID and name of the root tag. In flavors with a nameless
+    /// root, the name is empty.
+    fn read_root(&mut self) -> ParseResult<Event> {
+        let id = self.bytes.next_id()?;
+        let name = if id == TagID::End || !self.bytes.flavor.has_root_name() {
+            String::new()
+        } else {
+            self.bytes.next_str()?
+        };
+
+        self.pending = Some(id);
+        Ok(Event::Tag(id, name))
+    }
+
+    /// Reads a payload, or only the start of it for compounds and lists.
+    fn read_payload(&mut self, id: TagID) -> ParseResult<Event> {
+        self.bytes.expected = id;
+        self.bytes.charge(size_of::<TagPayload>())?;
+        let payload = match id {
+            TagID::End => TagPayload::End,
+            TagID::Byte => TagPayload::Byte(self.bytes.next_i8()?),
+            TagID::Short => TagPayload::Short(self.bytes.next_i16()?),
+            TagID::Int => TagPayload::Int(self.bytes.next_i32()?),
+            TagID::Long => TagPayload::Long(self.bytes.next_i64()?),
+            TagID::Float => TagPayload::Float(self.bytes.next_f32()?),
+            TagID::Double => TagPayload::Double(self.bytes.next_f64()?),
+            TagID::ByteArray => TagPayload::ByteArray(self.bytes.next_i8_array()?),
+            TagID::String => TagPayload::String(self.bytes.next_str()?),
+            TagID::List => {
+                self.bytes.check_depth(self.stack.len())?;
+                let (id, len) = self.bytes.next_list_header()?;
+                self.stack.push(Frame::List { id, len, read: 0 });
+                return Ok(Event::StartList(id, len));
+            }
+            TagID::Compound => {
+                self.bytes.check_depth(self.stack.len())?;
+                self.stack.push(Frame::Compound);
+                return Ok(Event::StartCompound);
+            }
+            TagID::IntArray => TagPayload::IntArray(self.bytes.next_i32_array()?),
+            TagID::LongArray => TagPayload::LongArray(self.bytes.next_i64_array()?),
+        };
+
+        self.finish_payload();
+        Ok(Event::Value(payload))
+    }
+
+    /// Leaves the path of a tag once its payload has been read in full.
+    fn finish_payload(&mut self) {
+        if !self.stack.is_empty() {
+            self.bytes.path.pop();
+        }
+    }
+}
+
+impl<R: Read> Iterator for EventReader<R> {
+    type Item = ParseResult<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
@@ -0,0 +1,183 @@
+use std::{collections::HashMap, fmt, slice::Iter};
+
+use super::{payload::TagPayload, Tag};
+
+#[derive(Debug)]
+pub enum CompoundError {
+    Missing(String),
+    Exists(String),
+}
+
+impl fmt::Display for CompoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "No tag named \"{name}\""),
+            Self::Exists(name) => write!(f, "A tag named \"{name}\" already exists"),
+        }
+    }
+}
+
+pub type CompoundResult<T> = Result<T, CompoundError>;
+
+/// What to do when a tag is added to a compound that already has a tag with
+/// the same name.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DuplicatePolicy {
+    /// Keep the tag that came first and drop the new one
+    First,
+    /// Replace the tag that came first with the new one, which is what
+    /// Minecraft does
+    #[default]
+    Last,
+    /// Refuse the new tag
+    Error,
+}
+
+/// The tags of a compound, in the order they were added, with an index from
+/// their names to their positions. The `TAG_End` that closes a compound in a
+/// file is not stored.
+#[derive(Clone, Debug, Default)]
+pub struct Compound {
+    tags: Vec<Tag>,
+    index: HashMap<String, usize>,
+}
+
+impl Compound {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, Tag> {
+        self.tags.iter()
+    }
+
+    pub fn as_slice(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        self.index.get(name).map(|&idx| &self.tags[idx])
+    }
+
+    /// Gets the payload of a tag to change it. The name can only be changed
+    /// with `rename`, which keeps the index up to date.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut TagPayload> {
+        self.index.get(name).map(|&idx| &mut self.tags[idx].payload)
+    }
+
+    /// Gets the position of a tag.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    /// Gets the tag at a position.
+    pub fn get_index(&self, idx: usize) -> Option<&Tag> {
+        self.tags.get(idx)
+    }
+
+    /// Adds a tag at the end, or replaces the tag with the same name where it
+    /// is, in which case the old tag is returned.
+    pub fn insert(&mut self, tag: Tag) -> Option<Tag> {
+        if let Some(&idx) = self.index.get(&tag.name) {
+            Some(std::mem::replace(&mut self.tags[idx], tag))
+        } else {
+            self.index.insert(tag.name.clone(), self.tags.len());
+            self.tags.push(tag);
+            None
+        }
+    }
+
+    /// Adds a tag at a position, moving the tags after it. Its name must not
+    /// be used yet.
+    pub fn insert_at(&mut self, idx: usize, tag: Tag) -> CompoundResult<()> {
+        if self.index.contains_key(&tag.name) {
+            return Err(CompoundError::Exists(tag.name));
+        }
+        for position in self.index.values_mut() {
+            if *position >= idx {
+                *position += 1;
+            }
+        }
+        self.index.insert(tag.name.clone(), idx);
+        self.tags.insert(idx, tag);
+        Ok(())
+    }
+
+    /// Adds a tag whose name may already be used, in which case `policy`
+    /// decides which of the two tags is kept. The tag that was left out is
+    /// returned.
+    pub fn insert_with(
+        &mut self,
+        tag: Tag,
+        policy: DuplicatePolicy,
+    ) -> CompoundResult<Option<Tag>> {
+        if !self.index.contains_key(&tag.name) {
+            return Ok(self.insert(tag));
+        }
+        match policy {
+            DuplicatePolicy::First => Ok(Some(tag)),
+            DuplicatePolicy::Last => Ok(self.insert(tag)),
+            DuplicatePolicy::Error => Err(CompoundError::Exists(tag.name)),
+        }
+    }
+
+    /// Removes a tag, keeping the order of the others.
+    pub fn remove(&mut self, name: &str) -> Option<Tag> {
+        let idx = self.index.remove(name)?;
+        for position in self.index.values_mut() {
+            if *position > idx {
+                *position -= 1;
+            }
+        }
+        Some(self.tags.remove(idx))
+    }
+
+    /// Renames a tag, keeping its position.
+    pub fn rename(&mut self, name: &str, new_name: String) -> CompoundResult<()> {
+        let idx = self
+            .position(name)
+            .ok_or_else(|| CompoundError::Missing(name.to_string()))?;
+        if new_name != name && self.index.contains_key(&new_name) {
+            return Err(CompoundError::Exists(new_name));
+        }
+
+        self.index.remove(name);
+        self.tags[idx].name.clone_from(&new_name);
+        self.index.insert(new_name, idx);
+        Ok(())
+    }
+}
+
+/// Compounds are equal if they have the same tags in the same order.
+impl PartialEq for Compound {
+    fn eq(&self, other: &Self) -> bool {
+        self.tags == other.tags
+    }
+}
+
+impl<'a> IntoIterator for &'a Compound {
+    type Item = &'a Tag;
+    type IntoIter = Iter<'a, Tag>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<Tag> for Compound {
+    fn from_iter<I: IntoIterator<Item = Tag>>(iter: I) -> Self {
+        let mut compound = Self::new();
+        for tag in iter {
+            compound.insert(tag);
+        }
+        compound
+    }
+}
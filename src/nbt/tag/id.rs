@@ -1,3 +1,7 @@
+use std::fmt;
+
+use crate::nbt::snbt::parse_payload;
+
 use super::payload::TagPayload;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -53,7 +57,7 @@ impl From<&TagPayload> for TagID {
             TagPayload::Double(_) => Self::Double,
             TagPayload::ByteArray(_) => Self::ByteArray,
             TagPayload::String(_) => Self::String,
-            TagPayload::List(_, _) => Self::List,
+            TagPayload::List(_) => Self::List,
             TagPayload::Compound(_) => Self::Compound,
             TagPayload::IntArray(_) => Self::IntArray,
             TagPayload::LongArray(_) => Self::LongArray,
@@ -61,7 +65,47 @@ impl From<&TagPayload> for TagID {
     }
 }
 
+impl fmt::Display for TagID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::End => "End",
+            Self::Byte => "Byte",
+            Self::Short => "Short",
+            Self::Int => "Int",
+            Self::Long => "Long",
+            Self::Float => "Float",
+            Self::Double => "Double",
+            Self::ByteArray => "ByteArray",
+            Self::String => "String",
+            Self::List => "List",
+            Self::Compound => "Compound",
+            Self::IntArray => "IntArray",
+            Self::LongArray => "LongArray",
+        })
+    }
+}
+
 impl TagID {
+    /// Gets the enum variant from its name, as printed by `Display`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "End" => Some(Self::End),
+            "Byte" => Some(Self::Byte),
+            "Short" => Some(Self::Short),
+            "Int" => Some(Self::Int),
+            "Long" => Some(Self::Long),
+            "Float" => Some(Self::Float),
+            "Double" => Some(Self::Double),
+            "ByteArray" => Some(Self::ByteArray),
+            "String" => Some(Self::String),
+            "List" => Some(Self::List),
+            "Compound" => Some(Self::Compound),
+            "IntArray" => Some(Self::IntArray),
+            "LongArray" => Some(Self::LongArray),
+            _ => None,
+        }
+    }
+
     pub fn is_container(self) -> bool {
         matches!(
             self,
@@ -75,7 +119,8 @@ impl TagID {
 
     fn parse_num(self, input: &str) -> Option<TagPayload> {
         if self.is_decimal() {
-            let num: f64 = input.parse().ok()?;
+            // Like SNBT, NaN and infinities aren't accepted
+            let num: f64 = input.parse().ok().filter(|n: &f64| n.is_finite())?;
             if let Self::Float = self {
                 Some(TagPayload::Float({
                     let res = num as f32;
@@ -131,6 +176,9 @@ impl TagID {
         None
     }
 
+    /// Parses a payload of this type. Plain numbers and double-quoted strings
+    /// are read as this type, and anything else is read as SNBT, which has to
+    /// produce a payload of this type.
     pub fn parse(self, input: &str) -> Option<TagPayload> {
         match self {
             TagID::Byte
@@ -142,5 +190,10 @@ impl TagID {
             TagID::String => Self::parse_str(input),
             _ => None,
         }
+        .or_else(|| {
+            parse_payload(input)
+                .ok()
+                .filter(|payload| TagID::from(payload) == self)
+        })
     }
 }
@@ -0,0 +1,153 @@
+use std::{fmt, slice::Iter};
+
+use super::{id::TagID, payload::TagPayload};
+
+#[derive(Debug)]
+pub enum ListError {
+    Type(TagID, TagID),
+    EndElement,
+    Index(usize),
+}
+
+impl fmt::Display for ListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Type(expected, found) => {
+                write!(f, "Expected {expected} in list but found {found}")
+            }
+            Self::EndElement => f.write_str("Lists cannot hold End tags"),
+            Self::Index(idx) => write!(f, "Invalid index: {idx}"),
+        }
+    }
+}
+
+pub type ListResult<T> = Result<T, ListError>;
+
+/// A list of payloads that all have the same type. An empty list has the
+/// type `TagID::End`, unless it was read from a file that gives it another
+/// one, and takes the type of the first element added to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct List {
+    id: TagID,
+    items: Vec<TagPayload>,
+}
+
+impl Default for List {
+    fn default() -> Self {
+        Self {
+            id: TagID::End,
+            items: vec![],
+        }
+    }
+}
+
+impl List {
+    /// Makes a list out of payloads that must all be of type `id`.
+    pub fn from_payloads(id: TagID, items: Vec<TagPayload>) -> ListResult<Self> {
+        for payload in &items {
+            let found = TagID::from(payload);
+            if found == TagID::End {
+                return Err(ListError::EndElement);
+            } else if found != id {
+                return Err(ListError::Type(id, found));
+            }
+        }
+        Ok(Self { id, items })
+    }
+
+    /// Gets the type of the elements.
+    pub fn id(&self) -> TagID {
+        self.id
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&TagPayload> {
+        self.items.get(idx)
+    }
+
+    /// Gets an element to change what it contains, such as the tags of a
+    /// compound in the list. Nothing outside the tree traversal may replace
+    /// the element through it, since that could change its type; replacing
+    /// is done with `set`, which checks the type.
+    pub(crate) fn get_mut(&mut self, idx: usize) -> Option<&mut TagPayload> {
+        self.items.get_mut(idx)
+    }
+
+    pub fn iter(&self) -> Iter<'_, TagPayload> {
+        self.items.iter()
+    }
+
+    pub fn as_slice(&self) -> &[TagPayload] {
+        &self.items
+    }
+
+    /// Checks that a payload can be added, which also gives an empty list
+    /// its type.
+    fn check(&mut self, payload: &TagPayload) -> ListResult<()> {
+        let id = TagID::from(payload);
+        if id == TagID::End {
+            Err(ListError::EndElement)
+        } else if self.is_empty() {
+            self.id = id;
+            Ok(())
+        } else if id == self.id {
+            Ok(())
+        } else {
+            Err(ListError::Type(self.id, id))
+        }
+    }
+
+    pub fn push(&mut self, payload: TagPayload) -> ListResult<()> {
+        self.check(&payload)?;
+        self.items.push(payload);
+        Ok(())
+    }
+
+    /// Inserts an element before the one at `idx`, or at the end if `idx` is
+    /// the length of the list.
+    pub fn insert(&mut self, idx: usize, payload: TagPayload) -> ListResult<()> {
+        if idx > self.len() {
+            return Err(ListError::Index(idx));
+        }
+        self.check(&payload)?;
+        self.items.insert(idx, payload);
+        Ok(())
+    }
+
+    /// Removes an element, keeping the type of the list even if it becomes
+    /// empty.
+    pub fn remove(&mut self, idx: usize) -> ListResult<TagPayload> {
+        if idx >= self.len() {
+            return Err(ListError::Index(idx));
+        }
+        Ok(self.items.remove(idx))
+    }
+
+    /// Replaces an element with a payload of the same type.
+    pub fn set(&mut self, idx: usize, payload: TagPayload) -> ListResult<()> {
+        if idx >= self.len() {
+            return Err(ListError::Index(idx));
+        }
+        // A single element can't change type either, since the list isn't
+        // empty while it's being replaced
+        self.check(&payload)?;
+        self.items[idx] = payload;
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a TagPayload;
+    type IntoIter = Iter<'a, TagPayload>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -1,10 +1,22 @@
-use crate::nbt::bytes::NbtBytesIter;
+use std::io::Read;
 
-use self::{id::TagID, payload::TagPayload};
+use crate::nbt::{
+    bytes::{ByteError, ParseError, ParseResult},
+    stream::{Event, EventReader},
+    Flavor,
+};
 
-use super::bytes::ByteResult;
+use self::{
+    compound::{Compound, CompoundError, DuplicatePolicy},
+    id::TagID,
+    list::List,
+    payload::TagPayload,
+    traversal::TagTraversal,
+};
 
+pub mod compound;
 pub mod id;
+pub mod list;
 pub mod payload;
 pub mod traversal;
 
@@ -15,99 +27,296 @@ pub struct Tag {
     pub payload: TagPayload,
 }
 
-impl Tag {
-    /// Consumes and returns every tag up to and including `TAG_End`.
-    fn get_compound(nbt_bytes: &mut NbtBytesIter) -> ByteResult<Vec<Self>> {
-        let mut tags = vec![];
+/// The deepest that compounds and lists may be nested in a file Minecraft
+/// accepts, counting from 0 at the root.
+pub const MAX_DEPTH: usize = 512;
 
-        loop {
-            let tag_id = nbt_bytes.next_id()?;
-            let name = if tag_id == TagID::End {
-                String::new()
-            } else {
-                nbt_bytes.next_str()?
-            };
+/// Settings for parsing binary NBT. The limits keep a malicious file from
+/// using up all the memory, and are loose enough for any real file. SNBT and
+/// JSON are read with the same duplicate policy.
+#[derive(Clone, Copy, Debug)]
+pub struct ParseOptions {
+    /// What to do with a tag whose name is already used in its compound
+    pub duplicates: DuplicatePolicy,
+    /// Largest size of the decompressed data, in bytes
+    pub max_size: usize,
+    /// Largest number of elements in a list or array
+    pub max_len: usize,
+    /// Largest amount of memory the parsed tags may take up, in bytes. Like
+    /// Minecraft's NBT accounter, every tag and element is charged for what
+    /// it takes up once parsed, so that a small file can't blow up into a
+    /// huge tree
+    pub max_memory: usize,
+    /// Deepest nesting of compounds and lists, which keeps the recursion
+    /// that builds and walks the tree from overflowing the stack
+    pub max_depth: usize,
+}
 
-            tags.push(Self {
-                id: tag_id,
-                name,
-                payload: Self::get_payload(nbt_bytes, tag_id)?,
-            });
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            duplicates: DuplicatePolicy::default(),
+            max_size: 256 << 20,
+            max_len: 1 << 26,
+            max_memory: 256 << 20,
+            max_depth: MAX_DEPTH,
+        }
+    }
+}
 
-            if tag_id == TagID::End {
-                break;
+/// A root tag parsed from binary NBT, SNBT or JSON.
+pub struct Parsed {
+    pub tag: Tag,
+    /// Number of bytes the tag took up
+    pub len: usize,
+    /// Paths of the tags whose name was already used in their compound, and
+    /// which were resolved by the `DuplicatePolicy`
+    pub duplicates: Vec<Vec<TagTraversal>>,
+}
+
+/// Builds a tree out of the events of an `EventReader`. In lenient mode, the
+/// first error is kept as the damage instead of being returned, and every
+/// container that was being read is closed with what was read before it.
+struct Builder<R> {
+    events: EventReader<R>,
+    options: ParseOptions,
+    lenient: bool,
+    damage: Option<ParseError>,
+    duplicates: Vec<Vec<TagTraversal>>,
+}
+
+impl<R: Read> Builder<R> {
+    /// Reads the next event, which must exist since the root is not done.
+    fn next(&mut self) -> ParseResult<Event> {
+        Ok(self.events.next_event()?.unwrap())
+    }
+
+    /// Records an error as the damage in lenient mode, otherwise returns it.
+    fn recover(&mut self, error: ParseError) -> ParseResult<()> {
+        if self.lenient {
+            self.damage.get_or_insert(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Builds the payload that starts with `event`.
+    fn payload(&mut self, event: Event) -> ParseResult<TagPayload> {
+        Ok(match event {
+            Event::Value(payload) => payload,
+            Event::StartCompound => TagPayload::Compound(self.compound()?),
+            // The reader only gives elements of the type of the list
+            Event::StartList(id, len) => {
+                TagPayload::List(List::from_payloads(id, self.list(len)?).unwrap())
             }
+            Event::Tag(..) | Event::End => unreachable!(),
+        })
+    }
+
+    /// Builds the next tag of a compound, or returns `None` at its end.
+    fn entry(&mut self) -> ParseResult<Option<Tag>> {
+        match self.next()? {
+            Event::Tag(id, name) => {
+                let event = self.next()?;
+                Ok(Some(Tag {
+                    id,
+                    name,
+                    payload: self.payload(event)?,
+                }))
+            }
+            Event::End => Ok(None),
+            _ => unreachable!(),
         }
+    }
 
-        Ok(tags)
-    }
-
-    /// Gets the payload to go with a tag type.
-    fn get_payload(nbt_bytes: &mut NbtBytesIter, tag_id: TagID) -> ByteResult<TagPayload> {
-        Ok(match tag_id {
-            TagID::End => TagPayload::End,
-            TagID::Byte => TagPayload::Byte(nbt_bytes.next_i8()?),
-            TagID::Short => TagPayload::Short(nbt_bytes.next_i16()?),
-            TagID::Int => TagPayload::Int(nbt_bytes.next_i32()?),
-            TagID::Long => TagPayload::Long(nbt_bytes.next_i64()?),
-            TagID::Float => TagPayload::Float(nbt_bytes.next_f32()?),
-            TagID::Double => TagPayload::Double(nbt_bytes.next_f64()?),
-            TagID::ByteArray => {
-                let mut payloads = vec![];
-                for _ in 0..nbt_bytes.next_i32()? {
-                    payloads.push(TagPayload::Byte(nbt_bytes.next_i8()?));
-                }
-
-                TagPayload::ByteArray(payloads)
+    /// Gets the path to a tag of the compound being read.
+    fn path_to(&self, name: String) -> Vec<TagTraversal> {
+        let mut path = self.events.path().to_vec();
+        path.push(TagTraversal::Compound(name));
+        path
+    }
+
+    /// Adds a tag that started at `offset` to a compound, following the
+    /// duplicate policy if its name is already used. In lenient mode, a tag
+    /// the policy refuses is left out and reported like other duplicates,
+    /// since it doesn't mean the rest of the file is damaged.
+    fn add(&mut self, compound: &mut Compound, tag: Tag, offset: usize) -> ParseResult<()> {
+        match compound.insert_with(tag, self.options.duplicates) {
+            Ok(None) => Ok(()),
+            Ok(Some(Tag { name, .. })) => {
+                let path = self.path_to(name);
+                self.duplicates.push(path);
+                Ok(())
             }
-            TagID::String => TagPayload::String(nbt_bytes.next_str()?),
-            TagID::List => {
-                let id = nbt_bytes.next_id()?;
-                let mut payloads = vec![];
-                for _ in 0..nbt_bytes.next_i32()? {
-                    payloads.push(Tag::get_payload(nbt_bytes, id)?);
-                }
-                TagPayload::List(id, payloads)
+            Err(CompoundError::Exists(name)) if self.lenient => {
+                let path = self.path_to(name);
+                self.duplicates.push(path);
+                Ok(())
+            }
+            Err(CompoundError::Exists(name)) => Err(ParseError {
+                path: self.path_to(name.clone()),
+                kind: ByteError::DuplicateTag(name),
+                offset,
+                expected: TagID::Compound,
+            }),
+            Err(CompoundError::Missing(_)) => unreachable!(),
+        }
+    }
+
+    /// Builds the tags of a compound, up to its `TAG_End`.
+    fn compound(&mut self) -> ParseResult<Compound> {
+        let mut compound = Compound::new();
+        loop {
+            let offset = self.events.offset();
+            let result = match self.entry() {
+                Ok(Some(tag)) => self.add(&mut compound, tag, offset),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                self.recover(e)?;
             }
-            TagID::Compound => TagPayload::Compound(Tag::get_compound(nbt_bytes)?),
-            TagID::IntArray => {
-                let mut payloads = vec![];
-                for _ in 0..nbt_bytes.next_i32()? {
-                    payloads.push(TagPayload::Int(nbt_bytes.next_i32()?));
-                }
-
-                TagPayload::IntArray(payloads)
+            if self.damage.is_some() {
+                break;
             }
-            TagID::LongArray => {
-                let mut payloads = vec![];
-                for _ in 0..nbt_bytes.next_i32()? {
-                    payloads.push(TagPayload::Long(nbt_bytes.next_i64()?));
-                }
+        }
+
+        Ok(compound)
+    }
 
-                TagPayload::LongArray(payloads)
+    /// Builds the next element of a list, or returns `None` at its end.
+    fn element(&mut self) -> ParseResult<Option<TagPayload>> {
+        match self.next()? {
+            Event::End => Ok(None),
+            event => self.payload(event).map(Some),
+        }
+    }
+
+    /// Builds the elements of a list of `len` elements.
+    fn list(&mut self, len: usize) -> ParseResult<Vec<TagPayload>> {
+        // The length comes from the file, so don't trust it for allocation
+        let mut payloads = Vec::with_capacity(len.min(1024));
+        loop {
+            match self.element() {
+                Ok(Some(payload)) => payloads.push(payload),
+                Ok(None) => break,
+                Err(e) => self.recover(e)?,
             }
-        })
+            if self.damage.is_some() {
+                break;
+            }
+        }
+
+        Ok(payloads)
     }
 
-    pub fn new(bytes: &[u8]) -> ByteResult<Self> {
-        let mut nbt_bytes = NbtBytesIter {
-            iter: &mut bytes.iter(),
-        };
-        let tag_id = nbt_bytes.next_id()?;
-        let name = if tag_id == TagID::End {
-            String::new()
-        } else {
-            nbt_bytes.next_str()?
+    fn root(&mut self) -> ParseResult<Parsed> {
+        let Event::Tag(id, name) = self.next()? else {
+            unreachable!()
         };
-
-        Ok(Self {
-            id: tag_id,
+        let event = self.next()?;
+        let tag = Tag {
+            id,
             name,
-            payload: Self::get_payload(&mut nbt_bytes, tag_id)?,
+            payload: self.payload(event)?,
+        };
+        Ok(Parsed {
+            tag,
+            len: self.events.offset(),
+            duplicates: std::mem::take(&mut self.duplicates),
         })
     }
+}
+
+impl Tag {
+    fn builder<R: Read>(
+        reader: R,
+        flavor: Flavor,
+        options: ParseOptions,
+        lenient: bool,
+    ) -> Builder<R> {
+        Builder {
+            events: EventReader::new(reader, flavor, options),
+            options,
+            lenient,
+            damage: None,
+            duplicates: vec![],
+        }
+    }
+
+    /// Parses a root tag from any reader, which should be buffered since it
+    /// is read a few bytes at a time.
+    pub fn parse(reader: impl Read, flavor: Flavor, options: ParseOptions) -> ParseResult<Parsed> {
+        Self::builder(reader, flavor, options, false).root()
+    }
+
+    /// Parses a root tag from a file that may be damaged, keeping everything
+    /// that could be read before the first error. The error is returned along
+    /// with the recovered tree, and this only fails if nothing was recovered.
+    pub fn parse_lenient(
+        bytes: &[u8],
+        flavor: Flavor,
+        options: ParseOptions,
+    ) -> ParseResult<(Parsed, Option<ParseError>)> {
+        let mut builder = Self::builder(bytes, flavor, options, true);
+        let parsed = builder.root()?;
+        Ok((parsed, builder.damage))
+    }
 
     pub fn is_container(&self) -> bool {
         self.id.is_container()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A root compound holding a list of `len` empty compounds, each of which
+    /// is a single `TAG_End` byte.
+    fn empty_compounds(len: i32) -> Vec<u8> {
+        let mut bytes = vec![0x0a, 0x00, 0x00, 0x09, 0x00, 0x01, b'l', 0x0a];
+        bytes.extend(len.to_be_bytes());
+        bytes.extend(vec![0x00; len as usize]);
+        bytes.push(0x00);
+        bytes
+    }
+
+    #[test]
+    fn small_file_cannot_blow_up_into_huge_tree() {
+        let bytes = empty_compounds(100_000);
+        let options = ParseOptions {
+            max_memory: 1 << 20,
+            ..ParseOptions::default()
+        };
+        assert!(bytes.len() < options.max_size);
+        let Err(e) = Tag::parse(bytes.as_slice(), Flavor::Java, options) else {
+            panic!("list of 100000 compounds fits in 1 MiB");
+        };
+        assert!(matches!(e.kind, ByteError::TooMuchMemory(max) if max == 1 << 20));
+        assert!(matches!(&e.path[0], TagTraversal::Compound(name) if name == "l"));
+    }
+
+    #[test]
+    fn memory_limit_allows_real_files() {
+        let bytes = empty_compounds(100_000);
+        let parsed = Tag::parse(bytes.as_slice(), Flavor::Java, ParseOptions::default()).unwrap();
+        let list = parsed.tag.payload.as_compound().unwrap().get("l").unwrap();
+        assert_eq!(list.payload.element_count(), Some(100_000));
+    }
+
+    #[test]
+    fn array_length_is_charged_before_reading() {
+        // A nameless int array claiming 2^20 varints, with none of them present
+        let bytes = [0x0b, 0x00, 0x80, 0x80, 0x80, 0x01];
+        let options = ParseOptions {
+            max_memory: 1 << 20,
+            ..ParseOptions::default()
+        };
+        let Err(e) = Tag::parse(bytes.as_slice(), Flavor::BedrockNetwork, options) else {
+            panic!("int array of 2^20 elements fits in 1 MiB");
+        };
+        assert!(matches!(e.kind, ByteError::TooMuchMemory(_)));
+    }
+}
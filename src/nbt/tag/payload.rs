@@ -2,7 +2,7 @@ use std::fmt::{self, Write};
 
 use enum_as_inner::EnumAsInner;
 
-use super::{Tag, TagID};
+use super::{compound::Compound, list::List};
 
 #[derive(Clone, Debug, EnumAsInner, PartialEq)]
 pub enum TagPayload {
@@ -13,12 +13,38 @@ pub enum TagPayload {
     Long(i64),
     Float(f32),
     Double(f64),
-    ByteArray(Vec<Self>),
+    ByteArray(Vec<i8>),
     String(String),
-    List(TagID, Vec<Self>),
-    Compound(Vec<Tag>),
-    IntArray(Vec<Self>),
-    LongArray(Vec<Self>),
+    List(List),
+    Compound(Compound),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl TagPayload {
+    /// Gets the number of elements of a list or array, or of tags in a
+    /// compound.
+    pub fn element_count(&self) -> Option<usize> {
+        match self {
+            Self::Compound(tags) => Some(tags.len()),
+            Self::ByteArray(v) => Some(v.len()),
+            Self::IntArray(v) => Some(v.len()),
+            Self::LongArray(v) => Some(v.len()),
+            Self::List(list) => Some(list.len()),
+            _ => None,
+        }
+    }
+
+    /// Gets a copy of an element of a list or array.
+    pub fn element(&self, idx: usize) -> Option<Self> {
+        match self {
+            Self::ByteArray(v) => v.get(idx).copied().map(Self::Byte),
+            Self::IntArray(v) => v.get(idx).copied().map(Self::Int),
+            Self::LongArray(v) => v.get(idx).copied().map(Self::Long),
+            Self::List(list) => list.get(idx).cloned(),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for TagPayload {
@@ -34,10 +60,8 @@ impl fmt::Display for TagPayload {
                 // Abbreviate if length is greater than 3, otherwise list
                 if bytes.len() <= 3 {
                     f.write_str("[B;")?;
-                    for b in bytes {
-                        if let Self::Byte(byte) = b {
-                            f.write_fmt(format_args!("{byte}b,"))?;
-                        }
+                    for byte in bytes {
+                        f.write_fmt(format_args!("{byte}b,"))?;
                     }
                     f.write_char(']')
                 } else {
@@ -45,11 +69,11 @@ impl fmt::Display for TagPayload {
                 }
             }
             Self::String(string) => f.write_fmt(format_args!("\"{string}\"")),
-            Self::List(_, payloads) => {
+            Self::List(list) => {
                 // Abbreviate if length is greater than 3, otherwise list
-                if payloads.len() <= 3 {
+                if list.len() <= 3 {
                     f.write_char('[')?;
-                    for p in payloads {
+                    for p in list {
                         p.fmt(f)?;
                         f.write_char(',')?;
                     }
@@ -63,10 +87,8 @@ impl fmt::Display for TagPayload {
                 // Abbreviate if length is greater than 3, otherwise list
                 if ints.len() <= 3 {
                     f.write_str("[I;")?;
-                    for i in ints {
-                        if let Self::Int(int) = i {
-                            f.write_fmt(format_args!("{int},"))?;
-                        }
+                    for int in ints {
+                        f.write_fmt(format_args!("{int},"))?;
                     }
                     f.write_char(']')
                 } else {
@@ -77,10 +99,8 @@ impl fmt::Display for TagPayload {
                 // Abbreviate if length is greater than 3, otherwise list
                 if longs.len() <= 3 {
                     f.write_str("[L;")?;
-                    for l in longs {
-                        if let Self::Long(long) = l {
-                            f.write_fmt(format_args!("{long}b,"))?;
-                        }
+                    for long in longs {
+                        f.write_fmt(format_args!("{long}L,"))?;
                     }
                     f.write_char(']')
                 } else {
@@ -1,8 +1,14 @@
 use enum_as_inner::EnumAsInner;
 
-use std::string::ToString;
+use std::{fmt, string::ToString};
 
-use super::{id::TagID, payload::TagPayload, Tag};
+use super::{
+    compound::{Compound, CompoundError, DuplicatePolicy},
+    id::TagID,
+    list::ListError,
+    payload::TagPayload,
+    Tag,
+};
 
 #[derive(EnumAsInner)]
 pub enum TraversedTag {
@@ -13,10 +19,6 @@ pub enum TraversedTag {
 }
 
 impl TraversedTag {
-    pub fn is_container(&self) -> bool {
-        matches!(self, Self::Tag(_) | Self::Payload(_))
-    }
-
     pub fn get_tag(self) -> Option<Tag> {
         match self {
             Self::ContainedTag(t) | Self::Tag(t) => Some(t),
@@ -36,16 +38,19 @@ impl TraversedTag {
 pub enum Error {
     Path(Vec<TagTraversal>),
     Index(i32),
+    Element(TagID),
+    List(ListError),
+    Compound(CompoundError),
 }
 
-impl ToString for Error {
-    fn to_string(&self) -> String {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Path(path) => {
-                let str_path: Vec<_> = path.iter().map(ToString::to_string).collect();
-                "Invalid path: ".to_string() + &str_path.join(" > ")
-            }
-            Self::Index(idx) => format!("Invalid index: {idx}"),
+            Self::Path(path) => write!(f, "Invalid path: {}", fmt_path(path)),
+            Self::Index(idx) => write!(f, "Invalid index: {idx}"),
+            Self::Element(id) => write!(f, "Invalid element for {id}"),
+            Self::List(e) => e.fmt(f),
+            Self::Compound(e) => e.fmt(f),
         }
     }
 }
@@ -57,43 +62,53 @@ pub enum TagTraversal {
     None,
 }
 
-impl ToString for TagTraversal {
-    fn to_string(&self) -> String {
+impl fmt::Display for TagTraversal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Compound(name) => name.clone(),
-            Self::Array(idx) => idx.to_string(),
-            Self::None => "None".to_string(),
+            Self::Compound(name) => f.write_str(name),
+            Self::Array(idx) => write!(f, "{idx}"),
+            Self::None => f.write_str("None"),
         }
     }
 }
 
+/// Formats a path the way it's shown in the breadcrumbs, or as "root" if it's
+/// empty.
+pub fn fmt_path(path: &[TagTraversal]) -> String {
+    if path.is_empty() {
+        "root".to_string()
+    } else {
+        let str_path: Vec<_> = path.iter().map(ToString::to_string).collect();
+        str_path.join(" > ")
+    }
+}
+
 pub fn traverse(path: &[TagTraversal], root: &Tag) -> Result<TraversedTag, Error> {
     // current selected tag
     let mut tag = Some(root);
     // current selected payload
     let mut payload = &tag.unwrap().payload;
-    for traversal in path {
+    for (i, traversal) in path.iter().enumerate() {
         match traversal {
             TagTraversal::Compound(name) => {
                 // Access name in compound
                 let subtags = payload.as_compound().unwrap();
-                tag = Some(
-                    subtags
-                        .iter()
-                        .find(|t| &t.name == name)
-                        .ok_or(Error::Path(path.to_vec()))?,
-                );
+                tag = Some(subtags.get(name).ok_or(Error::Path(path.to_vec()))?);
                 payload = &tag.unwrap().payload;
             }
             &TagTraversal::Array(idx) => {
-                // Access idx in array
-                let (
-                        TagPayload::IntArray(payloads)
-                        | TagPayload::ByteArray(payloads)
-                        | TagPayload::LongArray(payloads)
-                        | TagPayload::List(_, payloads)) = payload else {panic!("{payload:?}")};
-                payload = payloads.get(idx as usize).ok_or(Error::Index(idx))?;
-                tag = None;
+                if let TagPayload::List(list) = payload {
+                    // Access idx in list
+                    payload = list.get(idx as usize).ok_or(Error::Index(idx))?;
+                    tag = None;
+                } else {
+                    // Arrays only hold numbers, so this is the end of the path
+                    let element = payload.element(idx as usize).ok_or(Error::Index(idx))?;
+                    if i + 1 < path.len() {
+                        return Err(Error::Path(path.to_vec()));
+                    }
+                    return Ok(TraversedTag::ContainedPayload(element));
+                }
             }
             TagTraversal::None => unreachable!(),
         }
@@ -112,31 +127,227 @@ pub fn traverse(path: &[TagTraversal], root: &Tag) -> Result<TraversedTag, Error
     })
 }
 
-pub fn set(path: &[TagTraversal], root: &mut Tag, new: TagPayload) {
-    // current selected tag
-    let mut tag = Some(root);
+/// Replaces a number in an array, which must be of the type the array holds.
+fn set_element(array: &mut TagPayload, idx: usize, new: &TagPayload) -> Result<(), Error> {
+    match (array, new) {
+        (TagPayload::ByteArray(v), &TagPayload::Byte(n)) => v[idx] = n,
+        (TagPayload::IntArray(v), &TagPayload::Int(n)) => v[idx] = n,
+        (TagPayload::LongArray(v), &TagPayload::Long(n)) => v[idx] = n,
+        (array, _) => return Err(Error::Element(TagID::from(&*array))),
+    }
+    Ok(())
+}
+
+/// Replaces the payload at the end of a path. An element of an array can only
+/// be replaced by a number of the type the array holds.
+pub fn set(path: &[TagTraversal], root: &mut Tag, new: TagPayload) -> Result<(), Error> {
     // current selected payload
-    let mut payload = &mut tag.unwrap().payload;
-    for traversal in path {
+    let mut payload = &mut root.payload;
+    for (i, traversal) in path.iter().enumerate() {
         match traversal {
             TagTraversal::Compound(name) => {
                 // Access name in compound
                 let subtags = payload.as_compound_mut().unwrap();
-                tag = Some(subtags.iter_mut().find(|t| &t.name == name).unwrap());
-                payload = &mut tag.unwrap().payload;
+                if subtags.get(name).is_none() {
+                    return Err(Error::Path(path.to_vec()));
+                }
+                // Replacing the whole tag keeps its ID in sync if the type is
+                // being changed
+                if i == path.len() - 1 {
+                    subtags.insert(Tag {
+                        id: (&new).into(),
+                        name: name.clone(),
+                        payload: new,
+                    });
+                    return Ok(());
+                }
+                payload = subtags.get_mut(name).unwrap();
             }
             &TagTraversal::Array(idx) => {
+                let idx = idx as usize;
                 // Access idx in array
-                let (
-                        TagPayload::IntArray(payloads)
-                        | TagPayload::ByteArray(payloads)
-                        | TagPayload::LongArray(payloads)
-                        | TagPayload::List(_, payloads)) = payload else {panic!("{payload:?}")};
-                payload = payloads.get_mut(idx as usize).unwrap();
+                match payload {
+                    // Replacing an element of a list checks its type
+                    TagPayload::List(list) if i == path.len() - 1 => {
+                        return list.set(idx, new).map_err(Error::List);
+                    }
+                    TagPayload::List(list) => payload = list.get_mut(idx).unwrap(),
+                    array => return set_element(array, idx, &new),
+                }
             }
             TagTraversal::None => unreachable!(),
         }
     }
 
     *payload = new;
+    Ok(())
+}
+
+/// Gets the payload at the end of a path without copying it, to look at what
+/// a container holds. Like `container_mut`, it can't reach the numbers of
+/// arrays, which aren't stored as payloads.
+pub fn payload_at<'a>(path: &[TagTraversal], root: &'a Tag) -> Result<&'a TagPayload, Error> {
+    let mut payload = &root.payload;
+    for traversal in path {
+        payload = match (payload, traversal) {
+            (TagPayload::Compound(tags), TagTraversal::Compound(name)) => {
+                &tags
+                    .get(name)
+                    .ok_or_else(|| Error::Path(path.to_vec()))?
+                    .payload
+            }
+            (TagPayload::List(list), &TagTraversal::Array(idx)) => {
+                list.get(idx as usize).ok_or(Error::Index(idx))?
+            }
+            _ => return Err(Error::Path(path.to_vec())),
+        };
+    }
+    Ok(payload)
+}
+
+/// Gets the compound or list at the end of a path to change what it holds.
+/// Elements of lists are only reached through `List::get_mut`, and are never
+/// replaced here.
+fn container_mut<'a>(
+    path: &[TagTraversal],
+    root: &'a mut Tag,
+) -> Result<&'a mut TagPayload, Error> {
+    let mut payload = &mut root.payload;
+    for traversal in path {
+        payload = match (payload, traversal) {
+            (TagPayload::Compound(tags), TagTraversal::Compound(name)) => tags
+                .get_mut(name)
+                .ok_or_else(|| Error::Path(path.to_vec()))?,
+            (TagPayload::List(list), &TagTraversal::Array(idx)) => {
+                list.get_mut(idx as usize).ok_or(Error::Index(idx))?
+            }
+            _ => return Err(Error::Path(path.to_vec())),
+        };
+    }
+    Ok(payload)
+}
+
+/// Inserts a number into an array before the element at `idx`. It must be of
+/// the type the array holds.
+fn insert_element(array: &mut TagPayload, idx: usize, new: &TagPayload) -> Result<(), Error> {
+    if array.element_count().is_some_and(|len| idx > len) {
+        return Err(Error::Index(idx as i32));
+    }
+    match (array, new) {
+        (TagPayload::ByteArray(v), &TagPayload::Byte(n)) => v.insert(idx, n),
+        (TagPayload::IntArray(v), &TagPayload::Int(n)) => v.insert(idx, n),
+        (TagPayload::LongArray(v), &TagPayload::Long(n)) => v.insert(idx, n),
+        (array, _) => return Err(Error::Element(TagID::from(&*array))),
+    }
+    Ok(())
+}
+
+/// Adds a payload to the container at the end of `parent`, where `trav`
+/// says. In a compound it becomes a tag with the given name, which must not
+/// be taken yet, and is added at the end. In a list or array it's inserted
+/// before the element at the given index, and must be of the type of the
+/// other elements.
+pub fn insert(
+    parent: &[TagTraversal],
+    root: &mut Tag,
+    trav: &TagTraversal,
+    new: TagPayload,
+) -> Result<(), Error> {
+    match (container_mut(parent, root)?, trav) {
+        (TagPayload::Compound(tags), TagTraversal::Compound(name)) => {
+            let tag = Tag {
+                id: (&new).into(),
+                name: name.clone(),
+                payload: new,
+            };
+            tags.insert_with(tag, DuplicatePolicy::Error)
+                .map(|_| ())
+                .map_err(Error::Compound)
+        }
+        (TagPayload::List(list), &TagTraversal::Array(idx)) => {
+            list.insert(idx as usize, new).map_err(Error::List)
+        }
+        (array, &TagTraversal::Array(idx)) => insert_element(array, idx as usize, &new),
+        _ => Err(Error::Path(parent.to_vec())),
+    }
+}
+
+/// Adds a payload to the container at the end of `parent` like `insert`, but
+/// at position `idx` in a compound, to put back a tag where it was.
+pub fn insert_at(
+    parent: &[TagTraversal],
+    root: &mut Tag,
+    idx: usize,
+    trav: &TagTraversal,
+    new: TagPayload,
+) -> Result<(), Error> {
+    match (container_mut(parent, root)?, trav) {
+        (TagPayload::Compound(tags), TagTraversal::Compound(name)) => {
+            let tag = Tag {
+                id: (&new).into(),
+                name: name.clone(),
+                payload: new,
+            };
+            tags.insert_at(idx, tag).map_err(Error::Compound)
+        }
+        _ => insert(parent, root, trav, new),
+    }
+}
+
+/// Removes a number from an array.
+fn remove_element(array: &mut TagPayload, idx: usize) -> Result<TagPayload, Error> {
+    if array.element_count().is_none_or(|len| idx >= len) {
+        return Err(Error::Index(idx as i32));
+    }
+    Ok(match array {
+        TagPayload::ByteArray(v) => TagPayload::Byte(v.remove(idx)),
+        TagPayload::IntArray(v) => TagPayload::Int(v.remove(idx)),
+        TagPayload::LongArray(v) => TagPayload::Long(v.remove(idx)),
+        array => return Err(Error::Element(TagID::from(&*array))),
+    })
+}
+
+/// Removes the tag or element at `trav` from the container at the end of
+/// `parent`, and returns its payload.
+pub fn remove(
+    parent: &[TagTraversal],
+    root: &mut Tag,
+    trav: &TagTraversal,
+) -> Result<TagPayload, Error> {
+    match (container_mut(parent, root)?, trav) {
+        (TagPayload::Compound(tags), TagTraversal::Compound(name)) => tags
+            .remove(name)
+            .map(|tag| tag.payload)
+            .ok_or_else(|| Error::Compound(CompoundError::Missing(name.clone()))),
+        (TagPayload::List(list), &TagTraversal::Array(idx)) => {
+            list.remove(idx as usize).map_err(Error::List)
+        }
+        (array, &TagTraversal::Array(idx)) => remove_element(array, idx as usize),
+        _ => Err(Error::Path(parent.to_vec())),
+    }
+}
+
+/// Renames a tag of the compound at the end of `parent`, keeping its
+/// position. The new name must not be taken by another tag.
+pub fn rename(
+    parent: &[TagTraversal],
+    root: &mut Tag,
+    name: &str,
+    new_name: String,
+) -> Result<(), Error> {
+    match container_mut(parent, root)? {
+        TagPayload::Compound(tags) => tags.rename(name, new_name).map_err(Error::Compound),
+        _ => Err(Error::Path(parent.to_vec())),
+    }
+}
+
+/// Gets a name for a copy of a tag that isn't taken in its compound.
+pub fn copy_name(tags: &Compound, name: &str) -> String {
+    let mut copy = format!("{name}_copy");
+    let mut n = 1;
+    while tags.get(&copy).is_some() {
+        n += 1;
+        copy = format!("{name}_copy{n}");
+    }
+    copy
 }
@@ -1,18 +1,668 @@
-use crate::{args::Format, nbt::tag::Tag};
+use std::fmt;
+
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    args::Format,
+    nbt::{
+        encode::EncodeResult,
+        snbt::to_snbt,
+        tag::{
+            compound::{Compound, CompoundError},
+            id::TagID,
+            list::List,
+            payload::TagPayload,
+            traversal::{fmt_path, TagTraversal},
+            ParseOptions, Parsed, Tag,
+        },
+        Encoding,
+    },
+};
+
+#[derive(Debug)]
+pub enum JsonError {
+    Syntax(serde_json::Error),
+    MissingKey(Vec<TagTraversal>, &'static str),
+    UnknownType(Vec<TagTraversal>, String),
+    InvalidValue(Vec<TagTraversal>, TagID),
+    DuplicateTag(Vec<TagTraversal>),
+    TooDeep(Vec<TagTraversal>, usize),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(e) => write!(f, "Syntax error: {e}"),
+            Self::MissingKey(path, key) => {
+                write!(f, "Missing key \"{key}\" at {}", fmt_path(path))
+            }
+            Self::UnknownType(path, name) => {
+                write!(f, "Unknown tag type \"{name}\" at {}", fmt_path(path))
+            }
+            Self::InvalidValue(path, id) => {
+                write!(f, "Invalid value for {id} at {}", fmt_path(path))
+            }
+            Self::DuplicateTag(path) => write!(f, "Duplicate tag at {}", fmt_path(path)),
+            Self::TooDeep(path, max) => write!(
+                f,
+                "Tags are nested deeper than the limit of {max} at {}",
+                fmt_path(path)
+            ),
+        }
+    }
+}
+
+pub type JsonResult<T> = Result<T, JsonError>;
 
 pub fn get_ext(fmt: &Format) -> String {
     match fmt {
+        Format::Nbt => "nbt",
         Format::Json => "json",
+        Format::Snbt => "snbt",
     }
     .to_string()
 }
 
-pub fn translate(tag: &Tag, fmt: &Format) -> String {
+/// Converts a tag into the given format. `encoding` and `max_depth` only
+/// affect NBT, and `indent` only affects SNBT, which is written on a single
+/// line when it's `None`.
+pub fn translate(
+    tag: &Tag,
+    fmt: &Format,
+    encoding: &Encoding,
+    indent: Option<usize>,
+    max_depth: usize,
+) -> EncodeResult<Vec<u8>> {
     match fmt {
-        Format::Json => translate_json(tag),
+        Format::Nbt => encoding.encode(tag, max_depth),
+        Format::Json => Ok(translate_json(tag).into_bytes()),
+        Format::Snbt => Ok(to_snbt(tag, indent).into_bytes()),
+    }
+}
+
+/// Converts a float into a JSON value. JSON has no representation for
+/// non-finite numbers, so those are written as the strings `"NaN"`,
+/// `"Infinity"` and `"-Infinity"`.
+fn float_to_json(n: f64) -> Value {
+    if n.is_nan() {
+        "NaN".into()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.into()
+    } else {
+        n.into()
+    }
+}
+
+/// Converts a payload into its JSON value, without type information. The
+/// type of the payload must be known from the context it's in, which is
+/// either the `type` of an enclosing typed value or the `elementType` of an
+/// enclosing list.
+fn payload_to_json(payload: &TagPayload) -> Value {
+    match payload {
+        TagPayload::End => Value::Null,
+        TagPayload::Byte(n) => (*n).into(),
+        TagPayload::Short(n) => (*n).into(),
+        TagPayload::Int(n) => (*n).into(),
+        TagPayload::Long(n) => (*n).into(),
+        // Go through the shortest representation of the f32 so that 0.1f32 is
+        // written as 0.1 rather than 0.10000000149011612
+        TagPayload::Float(n) => float_to_json(n.to_string().parse().unwrap()),
+        TagPayload::Double(n) => float_to_json(*n),
+        TagPayload::String(s) => s.as_str().into(),
+        TagPayload::ByteArray(v) => v.as_slice().into(),
+        TagPayload::IntArray(v) => v.as_slice().into(),
+        TagPayload::LongArray(v) => v.as_slice().into(),
+        TagPayload::List(list) => json!({
+            "elementType": list.id().to_string(),
+            "items": list.iter().map(payload_to_json).collect::<Vec<_>>(),
+        }),
+        TagPayload::Compound(tags) => Value::Object(
+            tags.iter()
+                .map(|t| (t.name.clone(), typed_to_json(&t.payload)))
+                .collect::<Map<_, _>>(),
+        ),
     }
 }
 
+/// Converts a payload into a JSON object of the form
+/// `{"type": "Int", "value": 5}`.
+fn typed_to_json(payload: &TagPayload) -> Value {
+    json!({
+        "type": TagID::from(payload).to_string(),
+        "value": payload_to_json(payload),
+    })
+}
+
+/// Converts a tag into a JSON document that keeps all type information. The
+/// root is written as `{"name": "", "type": "Compound", "value": {...}}`,
+/// compounds map each name to a `{"type": ..., "value": ...}` object, and
+/// lists are written as `{"elementType": ..., "items": [...]}`.
 pub fn translate_json(tag: &Tag) -> String {
-    todo!()
+    serde_json::to_string_pretty(&json!({
+        "name": tag.name,
+        "type": TagID::from(&tag.payload).to_string(),
+        "value": payload_to_json(&tag.payload),
+    }))
+    .unwrap()
+}
+
+/// A JSON document as it was written. Unlike `Value`, which keeps only the
+/// last of several entries with the same key, objects keep all of their
+/// entries, so that repeated tag names can be resolved by the duplicate
+/// policy.
+enum Json {
+    Scalar(Value),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Gets the value of a key in an object, which is the last one if the
+    /// key is repeated.
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(entries) => entries.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Scalar(value) => value.as_i64(),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Scalar(value) => value.as_str(),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Self::Object(entries) => Some(entries),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scalar(value) => write!(f, "{value}"),
+            Self::Array(_) => f.write_str("[...]"),
+            Self::Object(_) => f.write_str("{...}"),
+        }
+    }
+}
+
+/// Reads a `Json` that may be nested at most `levels` more levels deep. The
+/// recursion limit of `serde_json` is disabled, since it's too low for deep
+/// tags, so this keeps a deeply nested document from overflowing the stack.
+#[derive(Clone, Copy)]
+struct JsonVisitor {
+    levels: usize,
+}
+
+impl JsonVisitor {
+    /// Gets the visitor for the values of an array or object.
+    fn nested<E: de::Error>(self) -> Result<Self, E> {
+        match self.levels.checked_sub(1) {
+            Some(levels) => Ok(Self { levels }),
+            None => Err(E::custom(
+                "JSON is nested deeper than the tag depth limit allows",
+            )),
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Json, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Json, E> {
+        Ok(Json::Scalar(Value::Null))
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Json, E> {
+        Ok(Json::Scalar(b.into()))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Json, E> {
+        Ok(Json::Scalar(n.into()))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Json, E> {
+        Ok(Json::Scalar(n.into()))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Json, E> {
+        Ok(Json::Scalar(n.into()))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Json, E> {
+        Ok(Json::Scalar(s.into()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json, A::Error> {
+        let nested = self.nested()?;
+        let mut items = vec![];
+        while let Some(item) = seq.next_element_seed(nested)? {
+            items.push(item);
+        }
+        Ok(Json::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+        let nested = self.nested()?;
+        let mut entries = vec![];
+        while let Some(key) = map.next_key()? {
+            entries.push((key, map.next_value_seed(nested)?));
+        }
+        Ok(Json::Object(entries))
+    }
+}
+
+/// Reads a float written by `float_to_json`.
+fn float_from_json(value: &Json) -> Option<f64> {
+    match value {
+        Json::Scalar(Value::Number(n)) => n.as_f64(),
+        Json::Scalar(Value::String(s)) => match s.as_str() {
+            "NaN" => Some(f64::NAN),
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reads an array of integers, or returns `None` if one of them does not fit
+/// in `T`.
+fn array_from_json<T: TryFrom<i64>>(value: &Json) -> Option<Vec<T>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| v.as_i64()?.try_into().ok())
+        .collect()
+}
+
+/// Reads a key out of a JSON object, or fails with `JsonError::MissingKey`.
+fn get_key<'a>(value: &'a Json, key: &'static str, path: &[TagTraversal]) -> JsonResult<&'a Json> {
+    value
+        .get(key)
+        .ok_or_else(|| JsonError::MissingKey(path.to_vec(), key))
+}
+
+/// Reads a tag type name, or fails with `JsonError::UnknownType`.
+fn id_from_json(value: &Json, path: &[TagTraversal]) -> JsonResult<TagID> {
+    let name = value
+        .as_str()
+        .ok_or_else(|| JsonError::UnknownType(path.to_vec(), value.to_string()))?;
+    TagID::from_name(name).ok_or_else(|| JsonError::UnknownType(path.to_vec(), name.to_string()))
+}
+
+/// The settings tags are read with, and the paths of the tags whose name was
+/// already used in their compound, which were resolved by the duplicate
+/// policy.
+struct Reader {
+    options: ParseOptions,
+    duplicates: Vec<Vec<TagTraversal>>,
+}
+
+/// The reverse of `payload_to_json`. Since JSON numbers don't carry a size,
+/// the type has to be provided, and values that don't fit in it are rejected
+/// rather than converted.
+fn payload_from_json(
+    id: TagID,
+    value: &Json,
+    path: &mut Vec<TagTraversal>,
+    reader: &mut Reader,
+) -> JsonResult<TagPayload> {
+    // Every enclosing container is a step of the path, like in binary NBT
+    let max_depth = reader.options.max_depth;
+    if id.is_container() && path.len() > max_depth {
+        return Err(JsonError::TooDeep(path.clone(), max_depth));
+    }
+    let invalid = |path: &[TagTraversal]| JsonError::InvalidValue(path.to_vec(), id);
+    let int = value.as_i64();
+    Ok(match id {
+        TagID::End => return Err(invalid(path)),
+        TagID::Byte => TagPayload::Byte(
+            int.and_then(|n| n.try_into().ok())
+                .ok_or_else(|| invalid(path))?,
+        ),
+        TagID::Short => TagPayload::Short(
+            int.and_then(|n| n.try_into().ok())
+                .ok_or_else(|| invalid(path))?,
+        ),
+        TagID::Int => TagPayload::Int(
+            int.and_then(|n| n.try_into().ok())
+                .ok_or_else(|| invalid(path))?,
+        ),
+        TagID::Long => TagPayload::Long(int.ok_or_else(|| invalid(path))?),
+        TagID::Float => {
+            // Parse the shortest representation directly so that the f32 is
+            // the one that was written by `payload_to_json`
+            let n = float_from_json(value).ok_or_else(|| invalid(path))?;
+            let float = if n.is_finite() {
+                n.to_string().parse::<f32>().unwrap()
+            } else {
+                n as f32
+            };
+            if n.is_finite() && !float.is_finite() {
+                return Err(invalid(path));
+            }
+            TagPayload::Float(float)
+        }
+        TagID::Double => TagPayload::Double(float_from_json(value).ok_or_else(|| invalid(path))?),
+        TagID::String => {
+            TagPayload::String(value.as_str().ok_or_else(|| invalid(path))?.to_string())
+        }
+        TagID::ByteArray => {
+            TagPayload::ByteArray(array_from_json(value).ok_or_else(|| invalid(path))?)
+        }
+        TagID::IntArray => {
+            TagPayload::IntArray(array_from_json(value).ok_or_else(|| invalid(path))?)
+        }
+        TagID::LongArray => {
+            TagPayload::LongArray(array_from_json(value).ok_or_else(|| invalid(path))?)
+        }
+        TagID::List => {
+            let element_id = id_from_json(get_key(value, "elementType", path)?, path)?;
+            let items = get_key(value, "items", path)?
+                .as_array()
+                .ok_or_else(|| invalid(path))?;
+            if element_id == TagID::End && !items.is_empty() {
+                return Err(invalid(path));
+            }
+
+            let mut payloads = vec![];
+            for (i, item) in items.iter().enumerate() {
+                path.push(TagTraversal::Array(i as i32));
+                payloads.push(payload_from_json(element_id, item, path, reader)?);
+                path.pop();
+            }
+            TagPayload::List(List::from_payloads(element_id, payloads).map_err(|_| invalid(path))?)
+        }
+        TagID::Compound => {
+            let mut tags = Compound::new();
+            for (name, typed) in value.as_object().ok_or_else(|| invalid(path))? {
+                path.push(TagTraversal::Compound(name.clone()));
+                let payload = typed_from_json(typed, path, reader)?;
+                let tag = Tag {
+                    id: (&payload).into(),
+                    name: name.clone(),
+                    payload,
+                };
+                match tags.insert_with(tag, reader.options.duplicates) {
+                    Ok(None) => (),
+                    Ok(Some(_)) => reader.duplicates.push(path.clone()),
+                    Err(CompoundError::Exists(_)) => {
+                        return Err(JsonError::DuplicateTag(path.clone()))
+                    }
+                    Err(CompoundError::Missing(_)) => unreachable!(),
+                }
+                path.pop();
+            }
+            TagPayload::Compound(tags)
+        }
+    })
+}
+
+/// The reverse of `typed_to_json`.
+fn typed_from_json(
+    value: &Json,
+    path: &mut Vec<TagTraversal>,
+    reader: &mut Reader,
+) -> JsonResult<TagPayload> {
+    let id = id_from_json(get_key(value, "type", path)?, path)?;
+    payload_from_json(id, get_key(value, "value", path)?, path, reader)
+}
+
+/// Parses a JSON document written by `translate_json` back into a tag. Tags
+/// with the same name as one before them in their compound are resolved by
+/// `options.duplicates`, and tags may be nested up to `options.max_depth`.
+pub fn parse_json(input: &str, options: ParseOptions) -> JsonResult<Parsed> {
+    // Each tag takes up at most 3 levels of JSON: the typed object of a tag
+    // in a compound, the object of a list and the array of its items
+    let visitor = JsonVisitor {
+        levels: options.max_depth.saturating_add(2).saturating_mul(3),
+    };
+    let mut deserializer = serde_json::Deserializer::from_str(input);
+    deserializer.disable_recursion_limit();
+    let root = visitor
+        .deserialize(&mut deserializer)
+        .map_err(JsonError::Syntax)?;
+    deserializer.end().map_err(JsonError::Syntax)?;
+    let mut path = vec![];
+    let name = get_key(&root, "name", &path)?
+        .as_str()
+        .ok_or(JsonError::MissingKey(vec![], "name"))?
+        .to_string();
+    let mut reader = Reader {
+        options,
+        duplicates: vec![],
+    };
+    let payload = typed_from_json(&root, &mut path, &mut reader)?;
+
+    Ok(Parsed {
+        tag: Tag {
+            id: (&payload).into(),
+            name,
+            payload,
+        },
+        len: input.len(),
+        duplicates: reader.duplicates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::nbt::{snbt::parse_snbt, tag::compound::DuplicatePolicy};
+
+    fn from_snbt(input: &str) -> Tag {
+        parse_snbt(input, ParseOptions::default()).unwrap().tag
+    }
+
+    fn round_trip(tag: &Tag) -> Tag {
+        parse_json(&translate_json(tag), ParseOptions::default())
+            .unwrap()
+            .tag
+    }
+
+    fn with_duplicates(input: &str, policy: DuplicatePolicy) -> JsonResult<Parsed> {
+        let options = ParseOptions {
+            duplicates: policy,
+            ..ParseOptions::default()
+        };
+        parse_json(input, options)
+    }
+
+    #[test]
+    fn every_type_round_trips() {
+        let tag = from_snbt(
+            r#"{byte:-5b,short:300s,int:5,long:1099511627776L,float:0.1f,double:-2.5d,
+            string:"\"quoted\"",bytes:[B;1b,-1b],ints:[I;7,-8],longs:[L;-9L],
+            list:[{a:1},{}],lists:[[1],[]],empty:[],compound:{nested:{}}}"#,
+        );
+        assert_eq!(round_trip(&tag), tag);
+        let json = translate_json(&tag);
+        assert!(json.contains(
+            r#""type": "Float",
+      "value": 0.1
+"#
+        ));
+    }
+
+    #[test]
+    fn non_finite_floats_round_trip() {
+        let mut tags = Compound::new();
+        for (name, payload) in [
+            ("nan", TagPayload::Double(f64::NAN)),
+            ("inf", TagPayload::Float(f32::INFINITY)),
+            ("-inf", TagPayload::Double(f64::NEG_INFINITY)),
+        ] {
+            tags.insert(Tag {
+                id: (&payload).into(),
+                name: name.to_string(),
+                payload,
+            });
+        }
+        let tag = Tag {
+            id: TagID::Compound,
+            name: String::new(),
+            payload: TagPayload::Compound(tags),
+        };
+        let read = round_trip(&tag);
+        let tags = read.payload.as_compound().unwrap();
+        assert!(tags
+            .get("nan")
+            .unwrap()
+            .payload
+            .as_double()
+            .unwrap()
+            .is_nan());
+        assert_eq!(
+            tags.get("inf").unwrap().payload,
+            TagPayload::Float(f32::INFINITY)
+        );
+        assert_eq!(
+            tags.get("-inf").unwrap().payload,
+            TagPayload::Double(f64::NEG_INFINITY)
+        );
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let input = r#"{"name": "", "type": "Compound", "value": {
+            "a": {"type": "Int", "value": 1},
+            "a": {"type": "Int", "value": 2}
+        }}"#;
+        let a = |parsed: &Parsed| {
+            parsed
+                .tag
+                .payload
+                .as_compound()
+                .unwrap()
+                .get("a")
+                .unwrap()
+                .payload
+                .clone()
+        };
+
+        let parsed = with_duplicates(input, DuplicatePolicy::Last).unwrap();
+        assert_eq!(a(&parsed), TagPayload::Int(2));
+        assert_eq!(parsed.duplicates.len(), 1);
+        assert_eq!(fmt_path(&parsed.duplicates[0]), "a");
+
+        let parsed = with_duplicates(input, DuplicatePolicy::First).unwrap();
+        assert_eq!(a(&parsed), TagPayload::Int(1));
+
+        let Err(e) = with_duplicates(input, DuplicatePolicy::Error) else {
+            panic!("duplicate tags were accepted");
+        };
+        assert!(matches!(e, JsonError::DuplicateTag(path) if fmt_path(&path) == "a"));
+    }
+
+    #[test]
+    fn invalid_values() {
+        let typed = |id: &str, value: &str| {
+            let value: Value = serde_json::from_str(value).unwrap();
+            let root = json!({"name": "", "type": "Compound", "value": {
+                "x": {"type": id, "value": value},
+            }});
+            parse_json(&root.to_string(), ParseOptions::default())
+        };
+        assert!(typed("Byte", "127").is_ok());
+        for (id, value) in [
+            ("Byte", "300"),
+            ("Int", "1.5"),
+            ("String", "1"),
+            ("ByteArray", "[1, 128]"),
+            ("List", r#"{"elementType": "End", "items": [1]}"#),
+        ] {
+            let Err(e) = typed(id, value) else {
+                panic!("{id} {value} was accepted");
+            };
+            assert!(
+                matches!(e, JsonError::InvalidValue(..)),
+                "{id} {value}: {e}"
+            );
+        }
+        let Err(e) = typed("Integer", "1") else {
+            panic!("an unknown type was accepted");
+        };
+        assert!(matches!(e, JsonError::UnknownType(_, name) if name == "Integer"));
+        let Err(e) = parse_json(
+            r#"{"name": "", "type": "Compound"}"#,
+            ParseOptions::default(),
+        ) else {
+            panic!("a missing value was accepted");
+        };
+        assert!(matches!(e, JsonError::MissingKey(_, "value")));
+    }
+
+    #[test]
+    fn deep_compounds_round_trip() {
+        let tag = from_snbt(&format!("{}1{}", "{a:".repeat(100), "}".repeat(100)));
+        assert_eq!(round_trip(&tag), tag);
+    }
+
+    #[test]
+    fn deepest_lists_round_trip() {
+        // Tags this deep need the stack of a main thread, which is larger
+        // than the one tests run on
+        thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(|| {
+                let depth = ParseOptions::default().max_depth;
+                let tag = from_snbt(&format!("{}{}", "[".repeat(depth), "]".repeat(depth)));
+                assert_eq!(round_trip(&tag), tag);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn too_deep() {
+        let options = ParseOptions {
+            max_depth: 10,
+            ..ParseOptions::default()
+        };
+        // The root is at depth 0, so 11 compounds reach the limit
+        let deepest = from_snbt(&format!("{}1{}", "{a:".repeat(11), "}".repeat(11)));
+        assert!(parse_json(&translate_json(&deepest), options).is_ok());
+
+        let tag = from_snbt(&format!("{}1{}", "{a:".repeat(12), "}".repeat(12)));
+        let Err(e) = parse_json(&translate_json(&tag), options) else {
+            panic!("compounds nested 12 deep were read with a limit of 10");
+        };
+        assert!(matches!(e, JsonError::TooDeep(path, 10) if path.len() == 11));
+    }
 }
@@ -0,0 +1,160 @@
+use crate::nbt::tag::{
+    payload::TagPayload,
+    traversal::{self, Error, TagTraversal},
+    Tag,
+};
+
+/// A change made to the tag being edited, with what's needed to take it back.
+#[derive(Clone)]
+pub enum Edit {
+    /// The payload at a path was replaced, possibly by one of another type
+    Set {
+        path: Vec<TagTraversal>,
+        old: TagPayload,
+        new: TagPayload,
+    },
+    /// A tag was added at position `idx` of the container at `parent`
+    Insert {
+        parent: Vec<TagTraversal>,
+        idx: usize,
+        trav: TagTraversal,
+        payload: TagPayload,
+    },
+    /// A tag was removed from position `idx` of the container at `parent`
+    Delete {
+        parent: Vec<TagTraversal>,
+        idx: usize,
+        trav: TagTraversal,
+        payload: TagPayload,
+    },
+    /// A tag of the compound at `parent` was renamed
+    Rename {
+        parent: Vec<TagTraversal>,
+        name: String,
+        new_name: String,
+    },
+}
+
+impl Edit {
+    /// Makes the change to a tag.
+    pub fn apply(&self, root: &mut Tag) -> Result<(), Error> {
+        match self {
+            Self::Set { path, new, .. } => traversal::set(path, root, new.clone()),
+            Self::Insert {
+                parent,
+                idx,
+                trav,
+                payload,
+            } => traversal::insert_at(parent, root, *idx, trav, payload.clone()),
+            Self::Delete { parent, trav, .. } => traversal::remove(parent, root, trav).map(|_| ()),
+            Self::Rename {
+                parent,
+                name,
+                new_name,
+            } => traversal::rename(parent, root, name, new_name.clone()),
+        }
+    }
+
+    /// Gets the path to the container the edit was made in.
+    pub fn container(&self) -> &[TagTraversal] {
+        match self {
+            Self::Set { path, .. } => &path[..path.len() - 1],
+            Self::Insert { parent, .. }
+            | Self::Delete { parent, .. }
+            | Self::Rename { parent, .. } => parent,
+        }
+    }
+
+    /// Gets the edit that takes this one back.
+    pub fn inverse(&self) -> Self {
+        match self.clone() {
+            Self::Set { path, old, new } => Self::Set {
+                path,
+                old: new,
+                new: old,
+            },
+            Self::Insert {
+                parent,
+                idx,
+                trav,
+                payload,
+            } => Self::Delete {
+                parent,
+                idx,
+                trav,
+                payload,
+            },
+            Self::Delete {
+                parent,
+                idx,
+                trav,
+                payload,
+            } => Self::Insert {
+                parent,
+                idx,
+                trav,
+                payload,
+            },
+            Self::Rename {
+                parent,
+                name,
+                new_name,
+            } => Self::Rename {
+                parent,
+                name: new_name,
+                new_name: name,
+            },
+        }
+    }
+}
+
+/// The edits made in the editor, which can be undone and redone.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+
+    // how many edits were in `undo` when the file was last written, and how
+    // many edits from then were undone and then dropped by a new edit
+    saved: (usize, usize),
+}
+
+impl History {
+    /// Records an edit that was just made. Edits that were undone can't be
+    /// redone after it.
+    pub fn push(&mut self, edit: Edit) {
+        let (depth, dropped) = self.saved;
+        if depth > self.undo.len() {
+            self.saved = (self.undo.len(), dropped + depth - self.undo.len());
+        }
+        self.redo.clear();
+        self.undo.push(edit);
+    }
+
+    /// Takes back the last edit, returning the edit that did so.
+    pub fn undo(&mut self, root: &mut Tag) -> Option<Edit> {
+        let edit = self.undo.last()?.inverse();
+        edit.apply(root).ok()?;
+        self.redo.push(self.undo.pop().unwrap());
+        Some(edit)
+    }
+
+    /// Makes the last undone edit again, returning it.
+    pub fn redo(&mut self, root: &mut Tag) -> Option<Edit> {
+        let edit = self.redo.last()?.clone();
+        edit.apply(root).ok()?;
+        self.undo.push(self.redo.pop().unwrap());
+        Some(edit)
+    }
+
+    /// Records that the tag as it is now was written.
+    pub fn mark_saved(&mut self) {
+        self.saved = (self.undo.len(), 0);
+    }
+
+    /// Gets how many edits away the tag is from when it was last written.
+    pub fn unsaved(&self) -> usize {
+        let (depth, dropped) = self.saved;
+        self.undo.len().abs_diff(depth) + dropped
+    }
+}
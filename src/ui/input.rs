@@ -1,35 +1,65 @@
-use crossterm::event::{self, Event, KeyCode, KeyEvent};
+use std::path::PathBuf;
 
-use crate::nbt::tag::{
-    id::TagID,
-    payload::TagPayload,
-    traversal::{set, traverse, TagTraversal},
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{
+    args::{fmt_encoding, to_encoding},
+    nbt::tag::{
+        id::TagID,
+        payload::TagPayload,
+        traversal::{copy_name, payload_at, traverse, TagTraversal},
+    },
 };
 
-use super::{EditMode, UI};
+use super::{history::Edit, EditMode, FileKind, Message, UI};
 
 pub enum Status {
     Ok,
     Quit,
     Change,
+    Insert,
+    Delete,
+    Rename,
+    Duplicate,
+    Undo,
+    Redo,
     Write,
+    SaveAs,
 }
 
 fn to_id(value: &str) -> Option<TagID> {
-    match value {
-        "Byte" => Some(TagID::Byte),
-        "Short" => Some(TagID::Short),
-        "Int" => Some(TagID::Int),
-        "Long" => Some(TagID::Long),
-        "Float" => Some(TagID::Float),
-        "Double" => Some(TagID::Double),
-        "String" => Some(TagID::String),
-        _ => None,
+    TagID::from_name(value).filter(|&id| id != TagID::End)
+}
+
+/// Edits the text of a prompt, with the cursor at `idx`.
+fn edit_text(text: &mut String, idx: &mut usize, code: KeyCode) {
+    match code {
+        KeyCode::Char(ch) => {
+            text.insert(*idx, ch);
+            *idx += ch.len_utf8();
+        }
+        KeyCode::Backspace if *idx > 0 => {
+            let ch = text[..*idx].chars().next_back().unwrap();
+            *idx -= ch.len_utf8();
+            text.remove(*idx);
+        }
+        KeyCode::Left => {
+            *idx -= text[..*idx].chars().next_back().map_or(0, char::len_utf8);
+        }
+        KeyCode::Right => *idx += text[*idx..].chars().next().map_or(0, char::len_utf8),
+        _ => (),
     }
 }
 
 impl UI<'_> {
     fn update_focused_tag(&mut self) {
+        // Nothing is focused in an empty container
+        if self.focused_trav.is_none() {
+            self.focused_payload = None;
+            self.focused_id = None;
+            return;
+        }
+
         self.focused_payload = Some(
             traverse(&self.get_full_trav(), self.tag)
                 .unwrap()
@@ -38,89 +68,337 @@ impl UI<'_> {
         self.focused_id = Some(self.focused_payload.as_ref().unwrap().into());
     }
 
-    pub fn move_focus(&mut self, forward: bool) {
-        let res = traverse(&self.selected_tag, self.tag).unwrap();
-        let selected_payload = res.get_payload();
+    /// Focuses the element of the selected container at `idx`, or its last
+    /// one if it has fewer, or nothing if it's empty.
+    fn focus_at(&mut self, idx: usize) {
+        let container = payload_at(&self.selected_tag, self.tag).unwrap();
+        let len = container.element_count().unwrap();
+        self.focused_trav = if len == 0 {
+            TagTraversal::None
+        } else if let TagPayload::Compound(subtags) = &container {
+            TagTraversal::Compound(subtags.get_index(idx.min(len - 1)).unwrap().name.clone())
+        } else {
+            TagTraversal::Array(idx.min(len - 1).try_into().unwrap())
+        };
+        self.update_focused_tag();
+    }
+
+    /// Gets the position of the focused tag in the selected container.
+    fn focused_idx(&self) -> Option<usize> {
         match &self.focused_trav {
+            TagTraversal::Compound(name) => payload_at(&self.selected_tag, self.tag)
+                .unwrap()
+                .as_compound()
+                .unwrap()
+                .position(name),
+            &TagTraversal::Array(idx) => Some(idx as usize),
+            TagTraversal::None => None,
+        }
+    }
+
+    pub fn move_focus(&mut self, forward: bool) {
+        let len = payload_at(&self.selected_tag, self.tag)
+            .unwrap()
+            .element_count()
+            .unwrap();
+        if len == 0 {
+            self.focused_trav = TagTraversal::None;
+            self.update_focused_tag();
+            return;
+        }
+        let idx = match (self.focused_idx(), forward) {
+            (Some(idx), true) => (idx + 1) % len,
+            (Some(idx), false) => (idx + len - 1) % len,
+            (None, true) => 0,
+            (None, false) => len - 1,
+        };
+        self.focus_at(idx);
+    }
+
+    /// Checks whether the selected container is the root of a region, whose
+    /// tags are its chunks, and says so in the status bar. Chunks can be
+    /// edited but not added, removed, renamed or copied, since only chunks
+    /// that are in the region are written back.
+    fn at_region_root(&mut self) -> bool {
+        let at_root = matches!(self.kind, FileKind::Region(_)) && self.selected_tag.is_empty();
+        if at_root {
+            self.message = Some(Message::Error(
+                "Chunks cannot be added, removed, renamed or copied".to_string(),
+            ));
+        }
+        at_root
+    }
+
+    /// Removes the focused tag, focusing the one that took its place, or the
+    /// one before it if it was the last.
+    pub fn delete(&mut self) {
+        let Some(idx) = self.focused_idx() else {
+            return;
+        };
+        if self.at_region_root() {
+            return;
+        }
+        let edit = Edit::Delete {
+            parent: self.selected_tag.clone(),
+            idx,
+            trav: self.focused_trav.clone(),
+            payload: self.focused_payload.clone().unwrap(),
+        };
+        if self.apply(edit) {
+            self.focus_at(idx);
+        }
+    }
+
+    /// Starts prompting for a new name for the focused tag, if it's in a
+    /// compound.
+    pub fn start_rename(&mut self) {
+        if self.at_region_root() {
+            return;
+        }
+        if let TagTraversal::Compound(name) = &self.focused_trav {
+            self.edit_mode = EditMode::Name(name.clone(), name.len());
+        }
+    }
+
+    /// Copies the focused tag, focusing the copy. A copy in a compound is
+    /// named after the original.
+    pub fn duplicate(&mut self) {
+        let Some(idx) = self.focused_idx() else {
+            return;
+        };
+        if self.at_region_root() {
+            return;
+        }
+        let trav = match &self.focused_trav {
             TagTraversal::Compound(name) => {
-                let subtags = selected_payload.as_compound().unwrap();
-                let idx = subtags
-                    .iter()
-                    // Remove Tag_END
-                    .filter(|t| t.id != TagID::End)
-                    .position(|t| &t.name == name)
-                    .unwrap();
-                self.focused_trav = TagTraversal::Compound(
-                    subtags[if forward {
-                        if subtags.len() <= idx + 2 {
-                            0
-                        } else {
-                            idx + 1
-                        }
-                    } else if idx == 0 {
-                        subtags.len() - 2
-                    } else {
-                        idx - 1
-                    }]
-                    .name
-                    .clone(),
-                );
+                let container = payload_at(&self.selected_tag, self.tag).unwrap();
+                TagTraversal::Compound(copy_name(container.as_compound().unwrap(), name))
             }
-            TagTraversal::Array(idx) => {
-                let payloads = selected_payload.as_list().unwrap().1;
-                self.focused_trav = TagTraversal::Array(if forward {
-                    if payloads.len() <= (idx + 1).try_into().unwrap() {
-                        0
-                    } else {
-                        idx + 1
-                    }
-                } else if *idx == 0 {
-                    payloads.len() as i32 - 1
-                } else {
-                    *idx - 1
+            TagTraversal::Array(idx) => TagTraversal::Array(idx + 1),
+            TagTraversal::None => return,
+        };
+        let edit = Edit::Insert {
+            parent: self.selected_tag.clone(),
+            idx: idx + 1,
+            trav: trav.clone(),
+            payload: self.focused_payload.clone().unwrap(),
+        };
+        if self.apply(edit) {
+            self.focused_trav = trav;
+            self.update_focused_tag();
+        }
+    }
+
+    /// Makes an edit and records it, returning whether it could be made.
+    fn apply(&mut self, edit: Edit) -> bool {
+        if edit.apply(self.tag).is_err() {
+            return false;
+        }
+        self.mark_changed(&edit);
+        self.history.push(edit);
+        true
+    }
+
+    /// Records which chunk of a region an edit was made in, so that it's
+    /// written back.
+    fn mark_changed(&mut self, edit: &Edit) {
+        // A chunk that's replaced as a whole is at the end of the path
+        let chunk = match edit {
+            Edit::Set { path, .. } => path.first(),
+            edit => edit.container().first(),
+        };
+        if let (FileKind::Region(region), Some(TagTraversal::Compound(name))) =
+            (&mut self.kind, chunk)
+        {
+            region.mark_changed(name);
+        }
+    }
+
+    /// Focuses the tag changed by an edit that was just made, or where it was
+    /// if it was removed.
+    fn show(&mut self, edit: &Edit) {
+        self.selected_tag = edit.container().to_vec();
+        match edit {
+            Edit::Set { path, .. } => self.focused_trav = path.last().unwrap().clone(),
+            Edit::Insert { trav, .. } => self.focused_trav = trav.clone(),
+            Edit::Delete { idx, .. } => return self.focus_at(*idx),
+            Edit::Rename { new_name, .. } => {
+                self.focused_trav = TagTraversal::Compound(new_name.clone());
+            }
+        }
+        self.update_focused_tag();
+    }
+
+    /// Takes back the last edit.
+    pub fn undo(&mut self) {
+        if let Some(edit) = self.history.undo(self.tag) {
+            self.mark_changed(&edit);
+            self.show(&edit);
+        }
+    }
+
+    /// Makes the last undone edit again.
+    pub fn redo(&mut self) {
+        if let Some(edit) = self.history.redo(self.tag) {
+            self.mark_changed(&edit);
+            self.show(&edit);
+        }
+    }
+
+    /// Starts prompting for a tag to add to the selected container: its name,
+    /// type and value in a compound, its type if it's the first element of a
+    /// list, and only its value otherwise. Elements are added after the
+    /// focused one.
+    pub fn start_insert(&mut self) {
+        if self.at_region_root() {
+            return;
+        }
+        let container = payload_at(&self.selected_tag, self.tag).unwrap();
+        let idx = self.focused_trav.as_array().map_or(0, |idx| idx + 1);
+        let (trav, id) = match &container {
+            TagPayload::Compound(_) => {
+                self.new_tag = Some(TagTraversal::Compound(String::new()));
+                self.edit_mode = EditMode::Name(String::new(), 0);
+                self.focused_id = None;
+                self.focused_payload = None;
+                return;
+            }
+            TagPayload::List(list) if list.is_empty() => (TagTraversal::Array(idx), None),
+            TagPayload::List(list) => (TagTraversal::Array(idx), Some(list.id())),
+            TagPayload::ByteArray(_) => (TagTraversal::Array(idx), Some(TagID::Byte)),
+            TagPayload::IntArray(_) => (TagTraversal::Array(idx), Some(TagID::Int)),
+            TagPayload::LongArray(_) => (TagTraversal::Array(idx), Some(TagID::Long)),
+            _ => unreachable!(),
+        };
+
+        self.new_tag = Some(trav);
+        self.focused_id = id;
+        self.focused_payload = None;
+        self.edit_mode = if id.is_some() {
+            EditMode::Value(String::new(), 0)
+        } else {
+            EditMode::Type(String::new(), 0)
+        };
+    }
+
+    /// Applies what was typed into the current prompt, moving on to the next
+    /// one. Input that isn't valid is left to be corrected.
+    fn submit(&mut self) {
+        match &self.edit_mode {
+            EditMode::None => (),
+            // Without a tag being added, the focused one is being renamed
+            EditMode::Name(text, _) if self.new_tag.is_none() => {
+                let name = self.focused_trav.as_compound().unwrap().clone();
+                let new_name = text.clone();
+                if name == new_name
+                    || self.apply(Edit::Rename {
+                        parent: self.selected_tag.clone(),
+                        name,
+                        new_name: new_name.clone(),
+                    })
+                {
+                    self.focused_trav = TagTraversal::Compound(new_name);
+                    self.edit_mode = EditMode::None;
+                }
+            }
+            EditMode::Name(text, _) => {
+                let container = payload_at(&self.selected_tag, self.tag).unwrap();
+                if container.as_compound().unwrap().get(text).is_none() {
+                    self.new_tag = Some(TagTraversal::Compound(text.clone()));
+                    self.edit_mode = EditMode::Type(String::new(), 0);
+                }
+            }
+            EditMode::Path(text, _) => {
+                if !text.is_empty() {
+                    self.save_path = Some(PathBuf::from(text.as_str()));
+                    let text = fmt_encoding(&self.encoding());
+                    self.edit_mode = EditMode::Encoding(text.clone(), text.len());
+                }
+            }
+            EditMode::Encoding(text, _) => {
+                let Some((fmt, encoding)) = to_encoding(text, self.encoding()) else {
+                    return;
+                };
+                let filename = self.save_path.take().unwrap();
+                let display = filename.display().to_string();
+                self.message = Some(match self.save_as(filename, &fmt, encoding) {
+                    Ok(()) => Message::Info(format!("Wrote {display}")),
+                    Err(e) => Message::Error(e),
                 });
+                self.edit_mode = EditMode::None;
             }
-            TagTraversal::None => match selected_payload {
-                TagPayload::Compound(subtags) => {
-                    // Account for Tag_END at the back
-                    if !subtags.len() > 1 {
-                        self.focused_trav = TagTraversal::Compound(
-                            subtags[if forward { 0 } else { subtags.len() - 2 }]
-                                .name
-                                .clone(),
-                        );
-                    }
+            EditMode::Type(text, _) => {
+                if let Some(id) = to_id(text) {
+                    self.focused_id = Some(id);
+                    self.focused_payload = None;
+                    self.edit_mode = EditMode::Value(String::new(), 0);
                 }
-                TagPayload::List(_, payloads) => {
-                    if !payloads.is_empty() {
-                        self.focused_trav = TagTraversal::Array(if forward {
-                            0
-                        } else {
-                            (payloads.len() - 1).try_into().unwrap()
-                        });
+            }
+            EditMode::Value(text, _) => {
+                let Some(payload) = self.focused_id.unwrap().parse(text) else {
+                    return;
+                };
+                // Array elements can't change type, and list elements have
+                // to match the others
+                let edit = if let Some(trav) = &self.new_tag {
+                    let container = payload_at(&self.selected_tag, self.tag).unwrap();
+                    // Tags are added to the end of a compound
+                    let idx = trav
+                        .as_array()
+                        .map_or_else(|| container.element_count().unwrap(), |&idx| idx as usize);
+                    Edit::Insert {
+                        parent: self.selected_tag.clone(),
+                        idx,
+                        trav: trav.clone(),
+                        payload,
+                    }
+                } else {
+                    Edit::Set {
+                        path: self.get_full_trav(),
+                        old: traverse(&self.get_full_trav(), self.tag)
+                            .unwrap()
+                            .get_payload(),
+                        new: payload,
+                    }
+                };
+                if self.apply(edit) {
+                    if let Some(trav) = self.new_tag.take() {
+                        self.focused_trav = trav;
                     }
+                    self.edit_mode = EditMode::None;
+                    self.update_focused_tag();
                 }
-                _ => unreachable!(),
-            },
+            }
         }
-
-        self.update_focused_tag();
     }
 
     pub fn get_events(&mut self) -> crossterm::Result<Status> {
-        if let Event::Key(KeyEvent { code, .. }) = event::read()? {
+        if let Event::Key(KeyEvent {
+            code, modifiers, ..
+        }) = event::read()?
+        {
+            // Any answer but yes cancels quitting
+            if self.quitting {
+                if code == KeyCode::Char('y') {
+                    return Ok(Status::Quit);
+                }
+                self.quitting = false;
+                self.message = None;
+                return Ok(Status::Ok);
+            }
+
             match &mut self.edit_mode {
                 EditMode::None => match code {
                     KeyCode::Char('q') => return Ok(Status::Quit),
-                    KeyCode::Enter => {
-                        if traverse(&self.get_full_trav(), self.tag)
-                            .unwrap()
-                            .is_container()
-                        {
-                            self.selected_tag.push(self.focused_trav.clone());
-                            self.focused_trav = TagTraversal::None;
-                            self.move_focus(true);
-                        }
+                    // Empty containers can be entered to add tags to them
+                    KeyCode::Enter
+                        if !self.focused_trav.is_none()
+                            && payload_at(&self.get_full_trav(), self.tag)
+                                .is_ok_and(|payload| payload.element_count().is_some()) =>
+                    {
+                        self.selected_tag.push(self.focused_trav.clone());
+                        self.focused_trav = TagTraversal::None;
+                        self.move_focus(true);
                     }
                     KeyCode::Esc => {
                         if let Some(trav) = self.selected_tag.pop() {
@@ -132,53 +410,44 @@ impl UI<'_> {
                     }
                     KeyCode::Tab | KeyCode::Down => self.move_focus(true),
                     KeyCode::BackTab | KeyCode::Up => self.move_focus(false),
-                    KeyCode::Char('c') => return Ok(Status::Change),
-                    KeyCode::Char('w') => return Ok(Status::Write),
-                    _ => (),
-                },
-
-                EditMode::Value(text, idx) => match code {
-                    KeyCode::Char(ch) => {
-                        text.insert(*idx, ch);
-                        *idx += 1;
-                    }
-                    KeyCode::Backspace => {
-                        *idx = idx.saturating_sub(1);
-                        text.remove(*idx);
+                    KeyCode::Char('c') if !self.focused_trav.is_none() => {
+                        return Ok(Status::Change)
                     }
-                    KeyCode::Left => *idx = idx.saturating_sub(1),
-                    KeyCode::Right => *idx += 1,
-                    KeyCode::Enter => {
-                        if let Some(payload) = self.focused_id.unwrap().parse(text) {
-                            self.focused_payload = Some(payload.clone());
-                            let mut tag = self.tag.clone();
-                            set(&self.get_full_trav(), &mut tag, payload);
-                            *self.tag = tag;
-                            self.edit_mode = EditMode::None;
-                        }
+                    KeyCode::Char('i') => return Ok(Status::Insert),
+                    KeyCode::Char('d') | KeyCode::Delete if !self.focused_trav.is_none() => {
+                        return Ok(Status::Delete)
                     }
-                    _ => (),
-                },
-                EditMode::Type(text, idx) => match code {
-                    KeyCode::Char(ch) => {
-                        text.insert(*idx, ch);
-                        *idx += 1;
+                    KeyCode::Char('u') => return Ok(Status::Undo),
+                    KeyCode::Char('r') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(Status::Redo)
                     }
-                    KeyCode::Backspace => {
-                        *idx = idx.saturating_sub(1);
-                        text.remove(*idx);
+                    KeyCode::Char('r') if self.focused_trav.as_compound().is_some() => {
+                        return Ok(Status::Rename)
                     }
-                    KeyCode::Left => *idx = idx.saturating_sub(1),
-                    KeyCode::Right => *idx += 1,
-                    KeyCode::Enter => {
-                        if let Some(id) = to_id(text) {
-                            self.focused_id = Some(id);
-                            self.focused_payload = None;
-                            self.edit_mode = EditMode::Value(String::new(), 0);
-                        }
+                    KeyCode::Char('y') if !self.focused_trav.is_none() => {
+                        return Ok(Status::Duplicate)
                     }
+                    KeyCode::Char('w') => return Ok(Status::Write),
+                    KeyCode::Char('s') => return Ok(Status::SaveAs),
                     _ => (),
                 },
+
+                EditMode::Name(text, idx)
+                | EditMode::Type(text, idx)
+                | EditMode::Value(text, idx)
+                | EditMode::Path(text, idx)
+                | EditMode::Encoding(text, idx) => match code {
+                    // Cancel the edit, going back to the type and value of the
+                    // focused tag
+                    KeyCode::Esc => {
+                        self.edit_mode = EditMode::None;
+                        self.new_tag = None;
+                        self.save_path = None;
+                        self.update_focused_tag();
+                    }
+                    KeyCode::Enter => self.submit(),
+                    code => edit_text(text, idx, code),
+                },
             }
         }
 
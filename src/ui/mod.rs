@@ -1,6 +1,6 @@
 use std::{
-    fs,
     io::{self, Stdout, Write},
+    panic,
     path::PathBuf,
 };
 
@@ -11,31 +11,87 @@ use crossterm::{
     },
 };
 use enum_as_inner::EnumAsInner;
-use flate2::{write::GzEncoder, Compression};
 
 use crate::{
+    args::Format,
     nbt::{
-        encode::encode_tag,
+        region::Region,
         tag::{id::TagID, payload::TagPayload, traversal::TagTraversal, Tag},
+        Encoding,
     },
-    util::UnwrapOrDisplayErr,
+    translate::translate,
+    util::write_file,
 };
 
-use self::{input::Status, win::Window};
+use self::{history::History, input::Status, win::Window};
 
+mod history;
 mod input;
 mod render;
 mod win;
 
+/// Puts the terminal back in its normal mode on the main screen.
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+}
+
+/// The terminal in raw mode on the alternate screen, which is restored when
+/// this is dropped, so that it's left usable however the editor stops.
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> crossterm::Result<Self> {
+        enable_raw_mode()?;
+        let terminal = Self;
+        execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+        // The message of a panic is printed before unwinding, and would be
+        // lost on the alternate screen
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+        Ok(terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
 #[derive(Clone, EnumAsInner)]
 pub enum EditMode {
     None,
+    Name(String, usize),
     Type(String, usize),
     Value(String, usize),
+    Path(String, usize),
+    Encoding(String, usize),
+}
+
+/// A message shown in the status bar.
+pub enum Message {
+    Info(String),
+    Error(String),
+}
+
+/// The kind of file being edited, which decides how it's written back.
+pub enum FileKind {
+    // a single tag, with the encoding it was read with
+    Nbt(Encoding),
+    // a region file, with a chunk at each entry of the root compound
+    Region(Region),
 }
 
 pub struct UI<'a> {
     filename: PathBuf,
+    kind: FileKind,
+    // deepest nesting the file may be written with, which is the limit it
+    // was read with
+    max_depth: usize,
     stdout: Stdout,
     tag: &'a mut Tag,
     breadcrumbs_win: Window,
@@ -53,13 +109,34 @@ pub struct UI<'a> {
     focused_id: Option<TagID>,
 
     edit_mode: EditMode,
+
+    // where the tag being added goes, while it's being prompted for
+    new_tag: Option<TagTraversal>,
+
+    history: History,
+
+    // where to save to, while the encoding is being prompted for
+    save_path: Option<PathBuf>,
+
+    // shown in the status bar, such as where a damaged file was cut short
+    message: Option<Message>,
+
+    // whether quitting is waiting to be confirmed, as there are unsaved changes
+    quitting: bool,
 }
 
 impl UI<'_> {
-    pub fn new(filename: PathBuf, tag: &mut Tag) -> crossterm::Result<UI> {
+    pub fn new(
+        filename: PathBuf,
+        kind: FileKind,
+        tag: &mut Tag,
+        max_depth: usize,
+    ) -> crossterm::Result<UI<'_>> {
         let size = terminal::size()?;
         Ok(UI {
             filename,
+            kind,
+            max_depth,
             stdout: io::stdout(),
             tag,
             breadcrumbs_win: Window::new(0, 0, 0, 1).unwrap(),
@@ -71,30 +148,85 @@ impl UI<'_> {
             focused_payload: None,
             focused_id: None,
             edit_mode: EditMode::None,
+            new_tag: None,
+            history: History::default(),
+            save_path: None,
+            message: None,
+            quitting: false,
         })
     }
 
+    /// Shows a message in the status bar.
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(Message::Error(message));
+    }
+
     fn get_full_trav(&self) -> Vec<TagTraversal> {
         let mut trav = self.selected_tag.clone();
         trav.push(self.focused_trav.clone());
         trav
     }
 
-    fn write(&self) {
-        let mut gz = GzEncoder::new(vec![], Compression::default());
-        gz.write_all(&encode_tag(self.tag))
-            .unwrap_or_err("Could not read tag");
+    /// Writes the tag back to the file by way of a temporary file, so a tag
+    /// which can't be encoded or a failed write leaves the file as it was.
+    fn write(&mut self) -> Result<(), String> {
+        match &mut self.kind {
+            FileKind::Nbt(encoding) => write_file(&self.filename, |writer| {
+                encoding
+                    .write(writer, self.tag, self.max_depth)
+                    .map(|_| ())
+                    .map_err(|e| format!("Could not encode: {e}"))
+            })?,
+            FileKind::Region(region) => {
+                region
+                    .update(self.tag)
+                    .map_err(|e| format!("Could not update region: {e}"))?;
+                write_file(&self.filename, |writer| {
+                    writer
+                        .write_all(region.as_bytes())
+                        .map_err(|e| format!("Could not write to file: {e}"))
+                })?;
+            }
+        }
+        self.history.mark_saved();
+        Ok(())
+    }
 
-        fs::write(
-            &self.filename,
-            gz.finish().unwrap_or_err("Could not unzip file"),
-        )
-        .unwrap_or_err("Could not write to file");
+    /// Gets the encoding the tag would be written with. Regions are saved as a
+    /// single gzipped Java file.
+    fn encoding(&self) -> Encoding {
+        match &self.kind {
+            FileKind::Nbt(encoding) => *encoding,
+            FileKind::Region(_) => Encoding::default(),
+        }
+    }
+
+    /// Writes the tag to another file in any format. Saving as NBT makes that
+    /// file the one being edited, while SNBT and JSON are only exported, since
+    /// the editor can't write them back.
+    fn save_as(
+        &mut self,
+        filename: PathBuf,
+        fmt: &Format,
+        encoding: Encoding,
+    ) -> Result<(), String> {
+        let bytes = translate(self.tag, fmt, &encoding, None, self.max_depth)
+            .map_err(|e| format!("Could not encode: {e}"))?;
+        write_file(&filename, |writer| {
+            writer
+                .write_all(&bytes)
+                .map_err(|e| format!("Could not write to file: {e}"))
+        })?;
+        if matches!(fmt, Format::Nbt) {
+            self.filename = filename;
+            self.kind = FileKind::Nbt(encoding);
+            self.history.mark_saved();
+        }
+        Ok(())
     }
 
     pub fn mainloop(&mut self) -> crossterm::Result<()> {
-        enable_raw_mode()?;
-        execute!(self.stdout, EnterAlternateScreen, cursor::Hide)?;
+        let _terminal = Terminal::enter()?;
 
         self.move_focus(true);
 
@@ -103,15 +235,34 @@ impl UI<'_> {
 
             #[allow(clippy::single_match)]
             match self.get_events()? {
+                Status::Quit if self.history.unsaved() > 0 && !self.quitting => {
+                    self.quitting = true;
+                    self.message = Some(Message::Error(
+                        "There are unsaved changes. Quit anyway? (y/n)".to_string(),
+                    ));
+                }
                 Status::Quit => break 'main,
                 Status::Ok => (),
                 Status::Change => self.edit_mode = EditMode::Type(String::new(), 0),
-                Status::Write => self.write(),
+                Status::Insert => self.start_insert(),
+                Status::Delete => self.delete(),
+                Status::Rename => self.start_rename(),
+                Status::Duplicate => self.duplicate(),
+                Status::SaveAs => {
+                    let path = self.filename.display().to_string();
+                    self.edit_mode = EditMode::Path(path.clone(), path.len());
+                }
+                Status::Undo => self.undo(),
+                Status::Redo => self.redo(),
+                Status::Write => {
+                    self.message = Some(match self.write() {
+                        Ok(()) => Message::Info(format!("Wrote {}", self.filename.display())),
+                        Err(e) => Message::Error(e),
+                    });
+                }
             }
         }
 
-        disable_raw_mode()?;
-        execute!(self.stdout, LeaveAlternateScreen, cursor::Show)?;
         Ok(())
     }
 }
@@ -6,12 +6,19 @@ use crossterm::{
     terminal::{Clear, ClearType},
 };
 
-use crate::nbt::tag::{id::TagID, payload::TagPayload, traversal::traverse};
+use crate::nbt::tag::{
+    id::TagID,
+    payload::TagPayload,
+    traversal::{payload_at, TagTraversal},
+};
 
-use super::{EditMode, UI};
+use super::{EditMode, Message, UI};
 
 fn get_value_display(id: TagID, payload: &TagPayload) -> StyledContent<String> {
-    let formatted = format!("{payload}");
+    style_value(id, format!("{payload}"))
+}
+
+fn style_value(id: TagID, formatted: String) -> StyledContent<String> {
     match id {
         TagID::Byte | TagID::Short | TagID::Int | TagID::Long => formatted.magenta(),
         TagID::Float | TagID::Double => formatted.dark_magenta(),
@@ -24,9 +31,39 @@ fn get_value_display(id: TagID, payload: &TagPayload) -> StyledContent<String> {
     }
 }
 
+fn get_type_display(id: TagID) -> StyledContent<&'static str> {
+    match id {
+        TagID::Byte => "Byte".magenta(),
+        TagID::Short => "Short".magenta(),
+        TagID::Int => "Int".magenta(),
+        TagID::Long => "Long".magenta(),
+        TagID::Float => "Float".dark_magenta(),
+        TagID::Double => "Double".dark_magenta(),
+        TagID::ByteArray => "ByteArray".dark_green(),
+        TagID::List => "List".dark_green(),
+        TagID::String => "String".cyan(),
+        TagID::Compound => "Compound".dark_red(),
+        TagID::IntArray => "IntArray".dark_green(),
+        TagID::LongArray => "LongArray".dark_green(),
+        TagID::End => unreachable!(),
+    }
+}
+
 impl UI<'_> {
-    fn render_array_type(&mut self, id: TagID, payloads: &[TagPayload]) -> crossterm::Result<()> {
-        for (i, payload) in payloads.iter().enumerate() {
+    fn render_array(&mut self) -> crossterm::Result<()> {
+        let payload = payload_at(&self.selected_tag, self.tag).unwrap();
+        let id = payload.into();
+        // Only the rows that fit are formatted, straight from the elements,
+        // since arrays can be very long
+        let rows = usize::from(self.tree_win.height());
+        let values: Vec<String> = match payload {
+            TagPayload::ByteArray(v) => v.iter().take(rows).map(|n| format!("{n}b")).collect(),
+            TagPayload::IntArray(v) => v.iter().take(rows).map(ToString::to_string).collect(),
+            TagPayload::LongArray(v) => v.iter().take(rows).map(|n| format!("{n}L")).collect(),
+            TagPayload::List(list) => list.iter().take(rows).map(ToString::to_string).collect(),
+            _ => unreachable!(),
+        };
+        for (i, value) in values.into_iter().enumerate() {
             self.tree_win
                 .mvwrite(&mut self.stdout, 0, i.try_into().unwrap(), "- ".grey())?
                 .write(&mut self.stdout, {
@@ -43,32 +80,15 @@ impl UI<'_> {
                     }
                 })?
                 .write(&mut self.stdout, ": ".grey())?
-                .write(&mut self.stdout, get_value_display(id, payload))?;
+                .write(&mut self.stdout, style_value(id, value))?;
         }
         Ok(())
     }
-    fn render_array(&mut self, payload: &TagPayload) -> crossterm::Result<()> {
-        self.render_array_type(
-            payload.into(),
-            match payload {
-                TagPayload::List(_, p)
-                | TagPayload::ByteArray(p)
-                | TagPayload::IntArray(p)
-                | TagPayload::LongArray(p) => p,
-                _ => unreachable!(),
-            },
-        )?;
-        Ok(())
-    }
 
-    fn render_compound(&mut self, payload: &TagPayload) -> crossterm::Result<()> {
-        for (i, subtag) in payload
-            .as_compound()
-            .unwrap()
-            .iter()
-            .filter(|t| t.id != TagID::End)
-            .enumerate()
-        {
+    fn render_compound(&mut self) -> crossterm::Result<()> {
+        let payload = payload_at(&self.selected_tag, self.tag).unwrap();
+        let rows = usize::from(self.tree_win.height());
+        for (i, subtag) in payload.as_compound().unwrap().iter().take(rows).enumerate() {
             self.tree_win
                 .mvwrite(&mut self.stdout, 0, i.try_into().unwrap(), "- ".grey())?
                 .write(&mut self.stdout, {
@@ -93,52 +113,106 @@ impl UI<'_> {
     }
 
     fn render_statusbar(&mut self) -> crossterm::Result<()> {
+        // Saving prompts take the place of the status bar
+        match &self.edit_mode {
+            EditMode::Path(input, _) => {
+                self.bottom_win
+                    .home(&mut self.stdout)?
+                    .write(&mut self.stdout, "Save as: ".bold().yellow())?
+                    .write(&mut self.stdout, input.as_str().stylize())?;
+                return Ok(());
+            }
+            EditMode::Encoding(input, _) => {
+                self.bottom_win
+                    .home(&mut self.stdout)?
+                    .write(&mut self.stdout, "Encoding: ".bold().yellow())?
+                    .write(&mut self.stdout, input.as_str().stylize())?
+                    .write(
+                        &mut self.stdout,
+                        " (snbt, json, or gzip/zlib/raw and java/bedrock/...)".grey(),
+                    )?;
+                return Ok(());
+            }
+            _ => (),
+        }
         self.bottom_win.home(&mut self.stdout)?.write(
             &mut self.stdout,
             format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
                 .bold()
                 .blue(),
         )?;
+        // The name of the file is marked while it has unsaved changes
+        self.bottom_win.write(
+            &mut self.stdout,
+            format!(" {}", self.filename.display()).stylize(),
+        )?;
+        if self.history.unsaved() > 0 {
+            self.bottom_win
+                .write(&mut self.stdout, "*".bold().yellow())?;
+        }
+        match self.history.unsaved() {
+            0 => (),
+            1 => {
+                self.bottom_win
+                    .write(&mut self.stdout, " 1 unsaved change".yellow())?;
+            }
+            n => {
+                self.bottom_win
+                    .write(&mut self.stdout, format!(" {n} unsaved changes").yellow())?;
+            }
+        }
+        if let Some(message) = &self.message {
+            self.bottom_win
+                .write(&mut self.stdout, " ".stylize())?
+                .write(
+                    &mut self.stdout,
+                    match message {
+                        Message::Info(message) => message.as_str().green(),
+                        Message::Error(message) => message.as_str().red(),
+                    },
+                )?;
+        }
         Ok(())
     }
 
     fn render_edit(&mut self) -> crossterm::Result<()> {
+        let mut row = 0;
+        // A tag being added to a compound is named first
+        let name = match (&self.edit_mode, &self.new_tag) {
+            (EditMode::Name(input, _), _) => Some(input.as_str()),
+            (_, Some(TagTraversal::Compound(name))) => Some(name.as_str()),
+            _ => None,
+        };
+        if let Some(name) = name {
+            self.edit_win
+                .mv(&mut self.stdout, 0, row)?
+                .write(&mut self.stdout, "Name: ".bold().yellow())?
+                .write(&mut self.stdout, name.stylize())?;
+            row += 1;
+        }
+
         self.edit_win
-            .home(&mut self.stdout)?
+            .mv(&mut self.stdout, 0, row)?
             .write(&mut self.stdout, "Type: ".bold().yellow())?
             .write(
                 &mut self.stdout,
                 if let EditMode::Type(input, _) = &self.edit_mode {
                     input.as_str().stylize()
                 } else {
-                    match self.focused_id.unwrap() {
-                        TagID::Byte => "Byte".magenta(),
-                        TagID::Short => "Short".magenta(),
-                        TagID::Int => "Int".magenta(),
-                        TagID::Long => "Long".magenta(),
-                        TagID::Float => "Float".dark_magenta(),
-                        TagID::Double => "Double".dark_magenta(),
-                        TagID::ByteArray => "ByteArray".dark_green(),
-                        TagID::List => "List".dark_green(),
-                        TagID::String => "String".cyan(),
-                        TagID::Compound => "Compound".dark_red(),
-                        TagID::IntArray => "IntArray".dark_green(),
-                        TagID::LongArray => "LongArray".dark_green(),
-                        TagID::End => unreachable!(),
-                    }
+                    self.focused_id
+                        .map_or_else(|| "".stylize(), get_type_display)
                 },
             )?
-            .nextline(&mut self.stdout)?
+            .mv(&mut self.stdout, 0, row + 1)?
             .write(&mut self.stdout, "Value: ".bold().yellow())?
             .write(
                 &mut self.stdout,
                 if let EditMode::Value(input, _) = &self.edit_mode {
-                    input.to_string().stylize()
+                    input.clone().stylize()
+                } else if let (Some(id), Some(payload)) = (self.focused_id, &self.focused_payload) {
+                    get_value_display(id, payload)
                 } else {
-                    get_value_display(
-                        self.focused_id.unwrap(),
-                        self.focused_payload.as_ref().unwrap(),
-                    )
+                    String::new().stylize()
                 },
             )?;
         Ok(())
@@ -146,12 +220,9 @@ impl UI<'_> {
 
     pub fn render(&mut self) -> crossterm::Result<()> {
         queue!(self.stdout, Clear(ClearType::All))?;
-        let payload = traverse(&self.selected_tag, self.tag)
-            .unwrap()
-            .get_payload();
-        match Into::<TagID>::into(&payload) {
-            TagID::Compound => self.render_compound(&payload)?,
-            _ => self.render_array(&payload)?,
+        match TagID::from(payload_at(&self.selected_tag, self.tag).unwrap()) {
+            TagID::Compound => self.render_compound()?,
+            _ => self.render_array()?,
         }
         self.breadcrumbs_win.mv(&mut self.stdout, 0, 0)?;
         for tr in &self.selected_tag {
@@ -167,7 +238,9 @@ impl UI<'_> {
                 .write(&mut self.stdout, " > ".dark_grey())?;
         }
         self.render_statusbar()?;
-        self.render_edit()?;
+        if self.focused_id.is_some() || self.new_tag.is_some() {
+            self.render_edit()?;
+        }
         self.stdout.flush()?;
         Ok(())
     }
@@ -1,4 +1,7 @@
-use std::{fmt::Display, io::Stdout};
+use std::{
+    fmt::{self, Display},
+    io::Stdout,
+};
 
 use crossterm::{
     cursor::MoveTo,
@@ -13,11 +16,11 @@ pub enum WindowError {
     InvalidDimensions,
 }
 
-impl ToString for WindowError {
-    fn to_string(&self) -> String {
+impl Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::CrosstermError(e) => format!("Crossterm error: {e}"),
-            Self::InvalidDimensions => "Invalid dimensions".to_string(),
+            Self::CrosstermError(e) => write!(f, "Crossterm error: {e}"),
+            Self::InvalidDimensions => f.write_str("Invalid dimensions"),
         }
     }
 }
@@ -26,7 +29,7 @@ pub struct Window {
     y: u16,
     x: u16,
     // w: u16,
-    // h: u16,
+    h: u16,
 }
 
 impl Window {
@@ -39,21 +42,22 @@ impl Window {
                 y,
                 x,
                 // w: if w == 0 { size.0 } else { w },
-                // h: if h == 0 { size.1 } else { h },
+                h: if h == 0 { size.1 } else { h },
             })
         }
     }
 
+    /// Gets the number of rows in the window.
+    pub fn height(&self) -> u16 {
+        self.h
+    }
+
     pub fn home(&mut self, stdout: &mut Stdout) -> crossterm::Result<&mut Self> {
         self.mv(stdout, 0, 0)
     }
 
     pub fn mv(&mut self, stdout: &mut Stdout, y: u16, x: u16) -> crossterm::Result<&mut Self> {
-        queue!(stdout, MoveTo(y + self.y, x + self.x)).map(|_| self)
-    }
-
-    pub fn nextline(&mut self, stdout: &mut Stdout) -> crossterm::Result<&mut Self> {
-        self.mv(stdout, 0, self.x)
+        queue!(stdout, MoveTo(y + self.y, x + self.x)).map(|()| self)
     }
 
     pub fn mvwrite<T: Display>(
@@ -68,7 +72,7 @@ impl Window {
             MoveTo(y + self.y, x + self.x),
             PrintStyledContent(s)
         )
-        .map(|_| self)
+        .map(|()| self)
     }
 
     pub fn write<T: Display>(
@@ -76,6 +80,6 @@ impl Window {
         stdout: &mut Stdout,
         s: StyledContent<T>,
     ) -> crossterm::Result<&mut Self> {
-        queue!(stdout, PrintStyledContent(s)).map(|_| self)
+        queue!(stdout, PrintStyledContent(s)).map(|()| self)
     }
 }
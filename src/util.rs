@@ -1,45 +1,69 @@
-use std::{fmt::Display, process::exit};
-
-fn display_error<E: Display>(msg: &str, err: E) -> ! {
-    eprintln!("{msg}: {err}");
-    exit(1);
-}
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    process::exit,
+};
 
 fn str_error<E: ToString>(msg: &str, err: &E) -> ! {
     eprintln!("{msg}: {}", err.to_string());
     exit(1);
 }
 
-pub trait UnwrapOrDisplayErr<E: Display> {
+pub trait UnwrapOrStrErr<E: ToString> {
     type Type;
 
     fn unwrap_or_err(self, msg: &str) -> Self::Type;
 }
 
-impl<T, E: Display> UnwrapOrDisplayErr<E> for Result<T, E> {
+impl<T, E: ToString> UnwrapOrStrErr<E> for Result<T, E> {
     type Type = T;
 
     fn unwrap_or_err(self, msg: &str) -> Self::Type {
         match self {
             Ok(v) => v,
-            Err(e) => display_error(msg, e),
+            Err(e) => str_error(msg, &e),
         }
     }
 }
 
-pub trait UnwrapOrStrErr<E: ToString> {
-    type Type;
-
-    fn unwrap_or_err(self, msg: &str) -> Self::Type;
-}
+/// Writes a file by way of a temporary file next to it, which is renamed over
+/// the original once it's complete. A write that fails part way leaves the
+/// original as it was. Devices and pipes such as `/dev/stdout` can't be
+/// replaced, so they're written to directly.
+pub fn write_file(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), String>,
+) -> Result<(), String> {
+    let io_error = |e: io::Error| format!("Could not write to file: {e}");
+    if fs::metadata(path).is_ok_and(|metadata| !metadata.is_file()) {
+        let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+        write(&mut writer)?;
+        return writer.flush().map_err(io_error);
+    }
 
-impl<T, E: ToString> UnwrapOrStrErr<E> for Result<T, E> {
-    type Type = T;
+    // Replace the file a link points to rather than the link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
 
-    fn unwrap_or_err(self, msg: &str) -> Self::Type {
-        match self {
-            Ok(v) => v,
-            Err(e) => str_error(msg, &e),
+    let result = File::create(&tmp).map_err(io_error).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| io_error(e.into_error()))?;
+        // Keep the permissions of the file being replaced
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())
+                .map_err(io_error)?;
         }
+        file.sync_all().map_err(io_error)?;
+        fs::rename(&tmp, &path).map_err(io_error)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
     }
+    result
 }